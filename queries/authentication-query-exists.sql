@@ -1,4 +1,4 @@
-SELECT p.oid FROM pg_catalog.pg_proc p
+SELECT p.oid, pg_catalog.obj_description(p.oid, 'pg_proc') as description FROM pg_catalog.pg_proc p
     INNER JOIN pg_catalog.pg_namespace n
         ON p.pronamespace = n.oid
     WHERE p.proname = 'user_lookup' AND n.nspname = 'pgbouncer';
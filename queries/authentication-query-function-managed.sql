@@ -0,0 +1,15 @@
+-- Sets up the user lookup function, refusing superusers, roles that cannot login, and roles with
+-- expired passwords. Only roles managed by external-postgres can be looked up
+CREATE OR REPLACE FUNCTION pgbouncer.user_lookup(in i_username text, out uname text, out phash text)
+    RETURNS record AS $$
+BEGIN
+    SELECT rolname, rolpassword FROM pg_catalog.pg_authid
+    WHERE rolname = i_username
+        AND rolcanlogin
+        AND NOT rolsuper
+        AND (rolvaliduntil IS NULL OR rolvaliduntil > pg_catalog.now())
+        AND pg_catalog.shobj_description(oid, 'pg_authid') = 'managed by external-postgres'
+    INTO uname, phash;
+    RETURN;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER SET search_path = pg_catalog;
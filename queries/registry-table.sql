@@ -0,0 +1,9 @@
+-- Keeps track of every database managed by external-postgres
+CREATE TABLE IF NOT EXISTS external_postgres.databases (
+    name text PRIMARY KEY,
+    owner text NOT NULL,
+    origin text NOT NULL,
+    cluster text,
+    retain boolean NOT NULL DEFAULT false,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
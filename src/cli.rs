@@ -36,12 +36,11 @@ impl Debug for Cli {
 #[command(rename_all = "kebab-case")]
 pub enum Command {
     /// Launch the server
-    Run(ServerArgs),
+    Run(Box<ServerArgs>),
     /// Manage databases
     #[command(subcommand)]
     Database(DatabaseCommand),
     /// Manage the operator
-    #[command(subcommand)]
     Operator(OperatorCommand),
     /// Check if the operator is health
     Health,
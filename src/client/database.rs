@@ -1,11 +1,14 @@
 use crate::{
     constants::APPLICATION_NAME,
-    models::database::{CreateRequest, DeleteOptions},
+    models::{
+        database::{CreateRequest, DeleteOptions, LookupFunctionVersion, ManagedDatabase},
+        ErrorResponse,
+    },
 };
 use clap::Subcommand;
 use eyre::{bail, WrapErr};
 use reqwest::{Client, StatusCode};
-use tracing::info;
+use tracing::{info, warn};
 use url::Url;
 
 #[derive(Debug, Subcommand)]
@@ -19,15 +22,36 @@ pub enum Command {
         name: String,
         /// The password for the associated user
         password: String,
+        /// The user that owns the database, defaults to the database's name
+        #[arg(short, long)]
+        username: Option<String>,
+        /// Retain the database's contents by default when it is removed
+        #[arg(long)]
+        retain: bool,
+        /// The cluster whose operator owns the database, required for databases managed by the
+        /// operator
+        #[arg(long)]
+        cluster: Option<String>,
     },
     /// Remove a database from management
     Remove {
         /// The database's name
         name: String,
-        /// Whether to retain the database's contents
+        /// Whether to retain the database's contents, defaults to the value set when ensured
         #[arg(long)]
-        retain: bool,
+        retain: Option<bool>,
+        /// The cluster whose operator owns the database, required for databases managed by the
+        /// operator
+        #[arg(long)]
+        cluster: Option<String>,
+    },
+    /// Rotate the generated password of a database managed by the operator
+    Rotate {
+        /// The database's name
+        name: String,
     },
+    /// Report which databases have an outdated PgBouncer lookup function
+    LookupFunction,
 }
 
 pub async fn client(address: Url, command: Command) -> eyre::Result<()> {
@@ -35,19 +59,39 @@ pub async fn client(address: Url, command: Command) -> eyre::Result<()> {
 
     let request = match &command {
         Command::List => client.get(address.join("/databases")?).build(),
-        Command::Ensure { name, password } => client
+        Command::Ensure {
+            name,
+            password,
+            username,
+            retain,
+            cluster,
+        } => client
             .post(address.join("/databases")?)
             .json(&CreateRequest {
                 name: name.clone(),
+                username: username.clone(),
                 password: password.clone(),
+                retain: *retain,
+                cluster: cluster.clone(),
             })
             .build(),
-        Command::Remove { name, retain } => client
+        Command::Remove {
+            name,
+            retain,
+            cluster,
+        } => client
             .delete(address.join(&format!("/databases/{name}"))?)
             .query(&DeleteOptions {
-                retain: Some(*retain),
+                retain: *retain,
+                cluster: cluster.clone(),
             })
             .build(),
+        Command::Rotate { name } => client
+            .post(address.join(&format!("/databases/{name}/rotate"))?)
+            .build(),
+        Command::LookupFunction => client
+            .get(address.join("/databases/lookup-function")?)
+            .build(),
     }
     .wrap_err("failed to build request")?;
 
@@ -59,17 +103,65 @@ pub async fn client(address: Url, command: Command) -> eyre::Result<()> {
     if response.status() == StatusCode::NOT_FOUND {
         bail!("database not found");
     }
+    if response.status() == StatusCode::CONFLICT {
+        let error = response.json::<ErrorResponse>().await?;
+        bail!("{}", error.message);
+    }
     let response = response
         .error_for_status()
         .wrap_err("unexpected status code")?;
 
     match command {
         Command::List => {
-            let databases = response.json::<Vec<String>>().await?;
-            info!(?databases);
+            let databases = response.json::<Vec<ManagedDatabase>>().await?;
+            for database in databases {
+                info!(
+                    %database.name,
+                    %database.owner,
+                    origin = database.origin.as_str(),
+                    cluster = database.cluster.as_deref().unwrap_or("none"),
+                    %database.retain,
+                    %database.created_at,
+                );
+            }
         }
         Command::Ensure { .. } => info!("ensured database exists"),
         Command::Remove { .. } => info!("database removed"),
+        Command::Rotate { .. } => info!("requested password rotation"),
+        Command::LookupFunction => {
+            let versions = response.json::<Vec<LookupFunctionVersion>>().await?;
+            for version in versions {
+                if let Some(error) = &version.error {
+                    warn!(%version.database, %error, "unreachable");
+                    continue;
+                }
+
+                let installed = version
+                    .installed
+                    .map(|installed| installed.to_string())
+                    .unwrap_or_else(|| String::from("missing"));
+
+                let installed_variant = version.installed_variant.as_deref().unwrap_or("none");
+
+                if version.is_outdated() {
+                    warn!(
+                        %version.database,
+                        %installed,
+                        %installed_variant,
+                        %version.latest,
+                        %version.variant,
+                        "outdated"
+                    );
+                } else {
+                    info!(
+                        %version.database,
+                        %installed,
+                        %installed_variant,
+                        "up-to-date"
+                    );
+                }
+            }
+        }
     }
 
     Ok(())
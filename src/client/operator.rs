@@ -1,45 +1,106 @@
 use crate::{
     constants::APPLICATION_NAME,
-    models::operator::{ChangeStateRequest, ChangeStateResponse, StateResponse, Status},
+    models::{
+        operator::{ChangeStateRequest, ChangeStateResponse, Configuration, StateResponse, Status},
+        ErrorResponse,
+    },
 };
-use clap::Subcommand;
-use eyre::WrapErr;
-use reqwest::Client;
+use clap::{Args, Subcommand};
+use eyre::{bail, WrapErr};
+use reqwest::{Client, Response};
+use std::path::PathBuf;
 use tracing::{info, warn};
 use url::Url;
 
+#[derive(Debug, Args)]
+pub struct Command {
+    /// The cluster whose operator to manage, defaults to the server's default cluster
+    #[arg(long, env = "KUBE_CLUSTER_NAME", global = true)]
+    cluster: Option<String>,
+
+    #[command(subcommand)]
+    action: Action,
+}
+
 #[derive(Debug, Subcommand)]
 #[command(rename_all = "kebab-case")]
-pub enum Command {
+pub enum Action {
+    /// List the state of the operator for every cluster
+    List,
     /// Enable the operator
     Enable,
     /// Disable the operator
     Disable,
     /// Check whether the operator is running
     Status,
+    /// Manage how the operator connects to Kubernetes
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Debug, Subcommand)]
+#[command(rename_all = "kebab-case")]
+pub enum ConfigCommand {
+    /// Show the kubeconfig and context in use
+    Get,
+    /// Change the kubeconfig or context, restarting the operator
+    Set {
+        /// The path to the kubeconfig file on the server
+        #[arg(short, long)]
+        kubeconfig: Option<PathBuf>,
+        /// The Kubernetes context to use
+        #[arg(short, long, conflicts_with = "current_context")]
+        context: Option<String>,
+        /// Use the kubeconfig's current context
+        #[arg(long)]
+        current_context: bool,
+    },
 }
 
 pub async fn client(address: Url, command: Command) -> eyre::Result<()> {
     let client = Client::builder().user_agent(APPLICATION_NAME).build()?;
+    let cluster = match &command.cluster {
+        Some(cluster) => address.join(&format!("/operators/{cluster}/"))?,
+        None => address.join("/operator/")?,
+    };
+
+    match command.action {
+        Action::List => list(address, client).await,
+        Action::Enable => change_state(cluster, Status::Enabled, client).await,
+        Action::Disable => change_state(cluster, Status::Disabled, client).await,
+        Action::Status => get_state(cluster, client).await,
+        Action::Config(ConfigCommand::Get) => get_config(cluster, client).await,
+        Action::Config(ConfigCommand::Set {
+            kubeconfig,
+            context,
+            current_context,
+        }) => set_config(cluster, kubeconfig, context, current_context, client).await,
+    }
+}
+
+async fn list(address: Url, client: Client) -> eyre::Result<()> {
+    let response = client
+        .get(address.join("/operators")?)
+        .send()
+        .await
+        .wrap_err("failed to send request")?;
+    let states: Vec<StateResponse> = check(response).await?.json().await?;
 
-    match command {
-        Command::Enable => change_state(address, Status::Enabled, client).await,
-        Command::Disable => change_state(address, Status::Disabled, client).await,
-        Command::Status => get_state(address, client).await,
+    for state in states {
+        log_state(state);
     }
+
+    Ok(())
 }
 
-async fn change_state(address: Url, desired: Status, client: Client) -> eyre::Result<()> {
+async fn change_state(cluster: Url, desired: Status, client: Client) -> eyre::Result<()> {
     let response = client
-        .post(address.join("/operator/state")?)
+        .post(cluster.join("state")?)
         .json(&ChangeStateRequest { desired })
         .send()
         .await
-        .wrap_err("failed to send request")?
-        .error_for_status()
-        .wrap_err("unexpected status code")?
-        .json::<ChangeStateResponse>()
-        .await?;
+        .wrap_err("failed to send request")?;
+    let response: ChangeStateResponse = check(response).await?.json().await?;
 
     match (desired, response.success) {
         (Status::Enabled, true) => info!("successfully enabled operator"),
@@ -50,21 +111,100 @@ async fn change_state(address: Url, desired: Status, client: Client) -> eyre::Re
     Ok(())
 }
 
-async fn get_state(address: Url, client: Client) -> eyre::Result<()> {
-    let response: StateResponse = client
-        .get(address.join("/operator/state")?)
+async fn get_state(cluster: Url, client: Client) -> eyre::Result<()> {
+    let response = client
+        .get(cluster.join("state")?)
         .send()
         .await
-        .wrap_err("failed to send request")?
-        .error_for_status()
-        .wrap_err("unexpected status code")?
-        .json()
-        .await?;
+        .wrap_err("failed to send request")?;
+    let state: StateResponse = check(response).await?.json().await?;
+
+    log_state(state);
 
-    match response.running {
-        true => info!("operator is running"),
-        false => info!("operator is stopped"),
+    Ok(())
+}
+
+fn log_state(response: StateResponse) {
+    let cluster = response.cluster;
+    let state = response.state.as_str();
+    match response.leader {
+        Some(leader) => info!(%cluster, %state, since = %response.since, %leader, "operator state"),
+        None => info!(%cluster, %state, since = %response.since, "operator state"),
     }
 
+    if let (Some(error), Some(at)) = (response.last_error, response.last_error_time) {
+        warn!(%cluster, %error, %at, "last error");
+    }
+}
+
+async fn get_config(cluster: Url, client: Client) -> eyre::Result<()> {
+    let response = client
+        .get(cluster.join("config")?)
+        .send()
+        .await
+        .wrap_err("failed to send request")?;
+    let config: Configuration = check(response).await?.json().await?;
+
+    log_config(&config);
+
+    Ok(())
+}
+
+async fn set_config(
+    cluster: Url,
+    kubeconfig: Option<PathBuf>,
+    context: Option<String>,
+    current_context: bool,
+    client: Client,
+) -> eyre::Result<()> {
+    let url = cluster.join("config")?;
+
+    // Anything not specified is kept as-is
+    let response = client
+        .get(url.clone())
+        .send()
+        .await
+        .wrap_err("failed to send request")?;
+    let existing: Configuration = check(response).await?.json().await?;
+    let desired = Configuration {
+        kubeconfig: kubeconfig.unwrap_or(existing.kubeconfig),
+        context: if current_context {
+            None
+        } else {
+            context.or(existing.context)
+        },
+    };
+
+    let response = client
+        .put(url)
+        .json(&desired)
+        .send()
+        .await
+        .wrap_err("failed to send request")?;
+    let config: Configuration = check(response).await?.json().await?;
+
+    info!("updated operator configuration");
+    log_config(&config);
+
     Ok(())
 }
+
+fn log_config(config: &Configuration) {
+    let kubeconfig = config.kubeconfig.display();
+    match &config.context {
+        Some(context) => info!(%kubeconfig, %context, "operator configuration"),
+        None => info!(%kubeconfig, context = "current", "operator configuration"),
+    }
+}
+
+/// Report the reason for any client errors, such as an unknown cluster or invalid configuration
+async fn check(response: Response) -> eyre::Result<Response> {
+    if response.status().is_client_error() {
+        let error = response.json::<ErrorResponse>().await?;
+        bail!("{}", error.message);
+    }
+
+    response
+        .error_for_status()
+        .wrap_err("unexpected status code")
+}
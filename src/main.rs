@@ -13,7 +13,7 @@ async fn main() -> eyre::Result<()> {
     debug!(?args);
 
     match args.command {
-        Command::Run(args) => server::launch(args).await,
+        Command::Run(args) => server::launch(*args).await,
         Command::Database(command) => client::database(args.address, command).await,
         Command::Operator(command) => client::operator(args.address, command).await,
         Command::Health => client::health(args.address).await,
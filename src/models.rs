@@ -7,26 +7,125 @@ pub struct ErrorResponse {
 }
 
 pub mod database {
+    use chrono::{DateTime, Utc};
     use serde::{Deserialize, Serialize};
 
     #[derive(Debug, Deserialize, Serialize)]
     pub struct CreateRequest {
         pub name: String,
+        pub username: Option<String>,
         pub password: String,
+        #[serde(default)]
+        pub retain: bool,
+        /// The cluster whose operator owns the database, required to modify databases managed by
+        /// the operator
+        #[serde(default)]
+        pub cluster: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct ManagedDatabase {
+        pub name: String,
+        pub owner: String,
+        pub origin: Origin,
+        /// The Kubernetes cluster whose operator owns the database
+        pub cluster: Option<String>,
+        pub retain: bool,
+        pub created_at: DateTime<Utc>,
+    }
+
+    #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum Origin {
+        Api,
+        Operator,
+    }
+
+    impl Origin {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                Self::Api => "api",
+                Self::Operator => "operator",
+            }
+        }
     }
 
     #[derive(Debug, Deserialize, Serialize)]
     pub struct DeleteOptions {
         pub retain: Option<bool>,
+        /// The cluster whose operator owns the database, required to remove databases managed by
+        /// the operator
+        pub cluster: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct LookupFunctionVersion {
+        pub database: String,
+        /// The installed version, or `None` if the function is missing
+        pub installed: Option<u32>,
+        pub installed_variant: Option<String>,
+        pub latest: u32,
+        pub variant: String,
+        /// Why the installed version could not be checked, if the database is unreachable
+        #[serde(default)]
+        pub error: Option<String>,
+    }
+
+    impl LookupFunctionVersion {
+        /// Whether the installed function is older than the latest version or is not the
+        /// configured variant
+        pub fn is_outdated(&self) -> bool {
+            self.installed
+                .map_or(true, |installed| installed < self.latest)
+                || self.installed_variant.as_ref() != Some(&self.variant)
+        }
     }
 }
 
 pub mod operator {
+    use chrono::{DateTime, Utc};
     use serde::{Deserialize, Serialize};
+    use std::path::PathBuf;
 
     #[derive(Debug, Deserialize, Serialize)]
     pub struct StateResponse {
+        /// The name of the cluster the controller manages
+        pub cluster: String,
         pub running: bool,
+        pub state: ControllerState,
+        /// When the controller entered the current state
+        pub since: DateTime<Utc>,
+        pub last_error: Option<String>,
+        pub last_error_time: Option<DateTime<Utc>>,
+        /// The identity of the instance holding the lease, if leader election is enabled
+        pub leader: Option<String>,
+    }
+
+    #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum ControllerState {
+        /// Connecting to the cluster
+        Starting,
+        /// Reconciling databases
+        Running,
+        /// Waiting for another instance to give up the lease
+        Following,
+        /// Failed to start, will be retried
+        Failed,
+        /// Not running
+        Stopped,
+    }
+
+    impl ControllerState {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                Self::Starting => "starting",
+                Self::Running => "running",
+                Self::Following => "following",
+                Self::Failed => "failed",
+                Self::Stopped => "stopped",
+            }
+        }
     }
 
     #[derive(Debug, Deserialize, Serialize)]
@@ -45,4 +144,12 @@ pub mod operator {
     pub struct ChangeStateResponse {
         pub success: bool,
     }
+
+    /// How the operator connects to Kubernetes
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct Configuration {
+        pub kubeconfig: PathBuf,
+        /// The context to use, defaults to the kubeconfig's current context
+        pub context: Option<String>,
+    }
 }
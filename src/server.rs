@@ -3,32 +3,51 @@ use clap::Args;
 use eyre::WrapErr;
 use std::{net::SocketAddr, path::PathBuf};
 use tokio::signal;
-use tracing::info;
+use tracing::{info, warn};
 
 mod database;
 mod http;
 mod operator;
 
 use database::Databases;
-use operator::Operator;
+use operator::{Cluster, Clusters};
 
 /// Launch the server
 pub async fn launch(args: ServerArgs) -> eyre::Result<()> {
-    let databases = Databases::new(&args.database)
+    let databases = Databases::new(&args.database, &args.pgbouncer)
         .await
         .wrap_err("failed to connect to database")?;
-    let kube = Operator::new(
-        args.kubeconfig,
-        args.kube_context,
-        args.operator,
+
+    // The instance that owns the auth file rotates the password itself, otherwise the leader of
+    // the primary cluster does when the credentials are published to a secret
+    if let Some(interval) = databases.auth_rotation_interval() {
+        if databases.manages_auth_password() {
+            tokio::spawn(databases.clone().rotate_auth_password_every(interval));
+        } else if args.auth_secret.name.is_none() {
+            warn!("not rotating the auth user password as its credentials are not published");
+        }
+    }
+
+    let clusters = Clusters::new(
+        &args.clusters,
+        Cluster {
+            name: args.clusters.cluster_name.clone(),
+            kubeconfig: args.kubeconfig,
+            context: args.kube_context,
+            connection_info: args.operator,
+        },
+        args.kube_config_source,
+        args.auth_secret,
+        args.controller,
         databases.clone(),
-    );
+    )
+    .wrap_err("failed to load clusters")?;
 
     // Launch the server
     info!(address = %args.management_address, "listening and ready to handle requests");
     Server::bind(&args.management_address)
-        .serve(http::router(databases, kube.clone()).into_make_service())
-        .with_graceful_shutdown(shutdown(kube))
+        .serve(http::router(databases, clusters.clone()).into_make_service())
+        .with_graceful_shutdown(shutdown(clusters))
         .await
         .wrap_err("failed to start server")?;
 
@@ -40,9 +59,21 @@ pub struct ServerArgs {
     #[command(flatten)]
     database: database::Options,
 
+    #[command(flatten)]
+    pgbouncer: database::PgBouncerOptions,
+
     #[command(flatten)]
     operator: operator::ConnectionInfo,
 
+    #[command(flatten)]
+    auth_secret: operator::AuthSecretOptions,
+
+    #[command(flatten)]
+    controller: operator::ControllerOptions,
+
+    #[command(flatten)]
+    clusters: operator::ClusterOptions,
+
     /// The address for the management server to listen on
     #[arg(
         short,
@@ -59,10 +90,14 @@ pub struct ServerArgs {
     /// The Kubernetes context to use
     #[arg(short = 'c', long, env = "KUBE_CONTEXT")]
     pub kube_context: Option<String>,
+
+    /// Where to load the Kubernetes configuration from
+    #[arg(long, value_enum, default_value_t, env = "KUBE_CONFIG_SOURCE")]
+    pub kube_config_source: operator::ConfigSource,
 }
 
 /// Wait for signals for terminating
-async fn shutdown(kube: Operator) {
+async fn shutdown(clusters: Clusters) {
     let ctrl_c = async {
         signal::ctrl_c()
             .await
@@ -82,7 +117,7 @@ async fn shutdown(kube: Operator) {
         _ = terminate => {},
     }
 
-    kube.stop().await;
+    clusters.stop().await;
 
     info!("server successfully shutdown");
     info!("goodbye! :)");
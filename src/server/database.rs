@@ -1,13 +1,39 @@
-use crate::constants::APPLICATION_NAME;
+use crate::{
+    constants::APPLICATION_NAME,
+    models::database::{LookupFunctionVersion, ManagedDatabase, Origin},
+};
+use chrono::Utc;
 use clap::Args;
 use parking_lot::RwLock;
 use sqlx::{
     postgres::{PgConnectOptions, PgPool, PgPoolOptions, PgSslMode},
-    query, query_file, query_file_as, ConnectOptions,
+    query, query_file_as, ConnectOptions,
 };
 use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
+use tokio::sync::watch;
 use tracing::{debug, error, info, instrument, log::LevelFilter, warn};
 
+mod drift;
+mod lookup;
+mod pgbouncer;
+mod quote;
+mod registry;
+
+pub use drift::Drift;
+pub use lookup::Variant as LookupFunction;
+use pgbouncer::PgBouncer;
+pub use pgbouncer::{Credentials, Options as PgBouncerOptions};
+use quote::{Identifier, Literal};
+
+/// The name of the registry key used to fingerprint secret contents
+const DIGEST_KEY: &str = "secret-digest";
+
+/// The name the auth user's last password rotation is recorded under
+const AUTH_ROTATION: &str = "auth-user";
+
+/// How long to wait before retrying a failed auth user password rotation
+const AUTH_ROTATION_RETRY: Duration = Duration::from_secs(60);
+
 #[derive(Debug, Args)]
 pub struct Options {
     /// The default database to connect to
@@ -45,6 +71,23 @@ pub struct Options {
         env = "DATABASE_SSL_MODE"
     )]
     pub ssl_mode: PgSslMode,
+
+    /// Which variant of the lookup function PgBouncer uses to authenticate users
+    #[arg(
+        long = "database-lookup-function",
+        value_enum,
+        default_value_t,
+        env = "DATABASE_LOOKUP_FUNCTION"
+    )]
+    pub lookup_function: LookupFunction,
+
+    /// The role PgBouncer authenticates as to lookup users
+    #[arg(
+        long = "database-auth-user",
+        default_value = "pgbouncer",
+        env = "DATABASE_AUTH_USER"
+    )]
+    pub auth_user: String,
 }
 
 /// Manage the connection pools of different databases on the specified server
@@ -55,13 +98,23 @@ pub struct Databases(Arc<DatabasesInner>);
 struct DatabasesInner {
     options: PgConnectOptions,
     pools: RwLock<HashMap<String, PgPool>>,
+    pgbouncer: PgBouncer,
+    /// The credentials of the auth user, unknown when its password is not managed
+    auth_credentials: watch::Sender<Option<Credentials>>,
+    /// Whether this instance owns the auth file, making it responsible for the auth user's password
+    manage_auth_password: bool,
+    auth_rotation_interval: Option<Duration>,
+    /// The key used to fingerprint secret contents, loaded from the registry during startup
+    digest_key: RwLock<Vec<u8>>,
 
     default_dbname: String,
     default_username: String,
+    auth_user: String,
+    lookup_function: LookupFunction,
 }
 
 impl Databases {
-    pub async fn new(opts: &Options) -> Result<Self> {
+    pub async fn new(opts: &Options, pgbouncer: &PgBouncerOptions) -> Result<Self> {
         // Construct the connection options
         let mut options = PgConnectOptions::new()
             .application_name(APPLICATION_NAME)
@@ -83,15 +136,22 @@ impl Databases {
         let databases = Databases(Arc::new(DatabasesInner {
             options,
             pools: RwLock::new(HashMap::new()),
+            pgbouncer: PgBouncer::new(pgbouncer, opts)?,
+            auth_credentials: watch::channel(None).0,
+            manage_auth_password: pgbouncer.auth_file.is_some(),
+            auth_rotation_interval: pgbouncer.auth_rotation_interval,
+            digest_key: RwLock::default(),
             default_dbname: opts.default_dbname.clone(),
             default_username: opts.username.clone(),
+            auth_user: opts.auth_user.clone(),
+            lookup_function: opts.lookup_function,
         }));
         databases.ensure_configuration(&opts.username).await?;
 
         Ok(databases)
     }
 
-    /// Ensure the pgbouncer user is setup and the connecting user has the correct permissions
+    /// Ensure the auth user is setup and the connecting user has the correct permissions
     #[instrument(skip(self))]
     async fn ensure_configuration(&self, connecting_user: &str) -> Result<()> {
         let default = self.get_default().await?;
@@ -109,41 +169,239 @@ impl Databases {
         }
         info!("current user has required permissions");
 
-        // Ensure the pgbouncer user exists
-        let pgbouncer = query_file_as!(User, "queries/user-permissions.sql", "pgbouncer")
+        // Ensure the auth user exists, reusing the stored password if there is one
+        let auth_user = query_file_as!(User, "queries/user-permissions.sql", &self.0.auth_user)
             .fetch_optional(&default)
             .await?;
-
-        if let Some(user) = pgbouncer {
+        if let Some(user) = &auth_user {
             info!(
                 %user.can_login,
                 %user.create_db,
                 %user.create_role,
                 %user.bypass_rls,
                 %user.superuser,
-                "pgbouncer user already exists"
+                "auth user already exists"
             );
-            if !user.can_login {
-                warn!("pgbouncer user should be able to login");
-            }
         } else {
-            warn!("pgbouncer user does not exist, creating...");
-            query!("CREATE USER pgbouncer WITH LOGIN NOSUPERUSER NOCREATEROLE NOCREATEDB NOREPLICATION NOBYPASSRLS")
-                .execute(&default)
+            warn!(name = %self.0.auth_user, "auth user does not exist, creating...");
+        }
+
+        // The password of an existing auth user is only replaced when this instance owns the auth
+        // file, otherwise a password that was set by hand would be lost. When the credentials are
+        // only published to a secret, the leader of the primary cluster takes over the password.
+        let credentials = match (self.0.pgbouncer.stored_credentials().await?, &auth_user) {
+            (Some(credentials), _) => Some(credentials),
+            (None, None) => Some(Credentials::generate(&self.0.auth_user)),
+            (None, Some(_)) if self.0.manage_auth_password => {
+                Some(Credentials::generate(&self.0.auth_user))
+            }
+            (None, Some(_)) => {
+                info!("auth file is not configured, leaving auth user password as-is");
+                None
+            }
+        };
+        if let Some(credentials) = credentials {
+            self.set_auth_credentials(credentials, auth_user.is_some(), &default)
                 .await?;
         }
 
         // Setup the default database for pgbouncer authentication just in case
-        ensure_schema(&default).await?;
-        ensure_authentication_query(&default).await?;
+        let auth_user = Identifier::new(&self.0.auth_user)?;
+        ensure_schema(&auth_user, &default).await?;
+        lookup::ensure(self.0.lookup_function, &auth_user, &default).await?;
+
+        registry::ensure_table(&default).await?;
+        *self.0.digest_key.write() = registry::key(DIGEST_KEY, &default).await?;
+        self.upgrade_lookup_functions(&auth_user).await?;
+        self.sync_pgbouncer(&default).await?;
+
+        Ok(())
+    }
+
+    /// Upgrade the lookup function in every managed database to the latest version, marking the
+    /// owners as managed. Failures are logged rather than returned so a single unreachable
+    /// database does not prevent startup.
+    #[instrument(skip_all)]
+    async fn upgrade_lookup_functions(&self, auth_user: &Identifier<'_>) -> Result<()> {
+        let default = self.get_default().await?;
+        for database in self.managed_databases().await? {
+            let result = async {
+                mark_managed(&database.owner, &default).await?;
+
+                let connection = self.get(&database.name).await?;
+                lookup::ensure(self.0.lookup_function, auth_user, &connection).await
+            }
+            .await;
+            if let Err(error) = result {
+                error!(%error, database = %database.name, "failed to upgrade lookup function");
+            }
+        }
 
         Ok(())
     }
 
+    /// Get the version of the lookup function installed in the default database and every managed
+    /// database, recording an error for any database that could not be checked
+    #[instrument(skip_all)]
+    pub async fn lookup_function_versions(&self) -> Result<Vec<LookupFunctionVersion>> {
+        let mut names = vec![self.0.default_dbname.clone()];
+        names.extend(
+            self.managed_databases()
+                .await?
+                .into_iter()
+                .map(|database| database.name),
+        );
+
+        let mut versions = Vec::with_capacity(names.len());
+        for name in names {
+            // An unreachable database is reported as such rather than failing the whole report
+            let result = async {
+                let connection = self.get(&name).await?;
+                lookup::installed(&connection).await
+            }
+            .await;
+            let (installed, error) = match result {
+                Ok(installed) => (installed, None),
+                Err(error) => {
+                    warn!(%error, database = %name, "failed to get lookup function version");
+                    (None, Some(error.to_string()))
+                }
+            };
+
+            versions.push(LookupFunctionVersion {
+                database: name,
+                installed: installed.as_ref().map(|installed| installed.version),
+                installed_variant: installed.map(|installed| installed.variant),
+                latest: lookup::VERSION,
+                variant: self.0.lookup_function.to_string(),
+                error,
+            });
+        }
+
+        Ok(versions)
+    }
+
     /// Get a list of all the managed databases
-    pub fn managed_databases(&self) -> Vec<String> {
-        let pools = self.0.pools.read();
-        pools.keys().map(Clone::clone).collect()
+    pub async fn managed_databases(&self) -> Result<Vec<ManagedDatabase>> {
+        let default = self.get_default().await?;
+        registry::list(&default).await
+    }
+
+    /// Get the details of a managed database
+    pub async fn managed_database(&self, database: &str) -> Result<ManagedDatabase> {
+        let default = self.get_default().await?;
+        registry::get(database, &default)
+            .await?
+            .ok_or(Error::NotManaged)
+    }
+
+    /// Get the current credentials of the auth user, notifying the receiver whenever they change
+    pub fn auth_credentials(&self) -> watch::Receiver<Option<Credentials>> {
+        self.0.auth_credentials.subscribe()
+    }
+
+    /// Whether this instance owns the auth file, making it responsible for the auth user's password
+    pub fn manages_auth_password(&self) -> bool {
+        self.0.manage_auth_password
+    }
+
+    /// The key used to fingerprint secret contents, shared by every instance using the same database
+    /// so fingerprints stored in the status stay comparable
+    pub fn digest_key(&self) -> Vec<u8> {
+        self.0.digest_key.read().clone()
+    }
+
+    /// The name of the auth user
+    pub fn auth_user(&self) -> &str {
+        &self.0.auth_user
+    }
+
+    /// How often the password for the auth user should be rotated
+    pub fn auth_rotation_interval(&self) -> Option<Duration> {
+        self.0.auth_rotation_interval
+    }
+
+    /// Change the password of the auth user to one that was already published, so a new leader
+    /// can take over the password without invalidating the existing credentials
+    #[instrument(skip(self))]
+    pub async fn adopt_auth_credentials(&self, credentials: Credentials) -> Result<()> {
+        let default = self.get_default().await?;
+        self.set_auth_credentials(credentials, true, &default)
+            .await?;
+        info!("adopted published auth user credentials");
+
+        Ok(())
+    }
+
+    /// Generate a new password for the auth user
+    #[instrument(skip(self))]
+    pub async fn rotate_auth_password(&self) -> Result<()> {
+        let default = self.get_default().await?;
+        let credentials = Credentials::generate(&self.0.auth_user);
+        self.set_auth_credentials(credentials, true, &default)
+            .await?;
+        registry::record_rotation(AUTH_ROTATION, &default).await?;
+        info!("rotated auth user password");
+
+        Ok(())
+    }
+
+    /// Periodically rotate the password for the auth user. The schedule is based on when the
+    /// password was last rotated by any instance, so restarts and changes of leader do not delay it.
+    pub async fn rotate_auth_password_every(self, interval: Duration) {
+        loop {
+            match self.auth_password_age().await {
+                Ok(age) if age < interval => tokio::time::sleep(interval - age).await,
+                Ok(_) => {
+                    if let Err(error) = self.rotate_auth_password().await {
+                        error!(%error, "failed to rotate auth user password");
+                        tokio::time::sleep(AUTH_ROTATION_RETRY).await;
+                    }
+                }
+                Err(error) => {
+                    error!(%error, "failed to get when the auth user password was last rotated");
+                    tokio::time::sleep(AUTH_ROTATION_RETRY).await;
+                }
+            }
+        }
+    }
+
+    /// How long ago the password for the auth user was last rotated
+    async fn auth_password_age(&self) -> Result<Duration> {
+        let default = self.get_default().await?;
+        let rotated_at = registry::rotated_at(AUTH_ROTATION, &default).await?;
+
+        // A rotation that appears to be in the future due to clock skew is treated as just happening
+        Ok((Utc::now() - rotated_at).to_std().unwrap_or_default())
+    }
+
+    /// Set the password for the auth user, storing and publishing the new credentials
+    async fn set_auth_credentials(
+        &self,
+        credentials: Credentials,
+        exists: bool,
+        default: &PgPool,
+    ) -> Result<()> {
+        let name = Identifier::new(&credentials.username)?;
+        let password = Literal::new(&credentials.password)?;
+        let sql = match exists {
+            true => format!("ALTER USER {name} WITH LOGIN PASSWORD {password}"),
+            false => format!("CREATE USER {name} WITH LOGIN NOSUPERUSER NOCREATEROLE NOCREATEDB NOREPLICATION NOBYPASSRLS PASSWORD {password}"),
+        };
+        query(&sql).execute(default).await?;
+
+        self.0.pgbouncer.write_credentials(&credentials).await?;
+        self.0.auth_credentials.send_replace(Some(credentials));
+
+        Ok(())
+    }
+
+    /// Update the PgBouncer configuration to match the managed databases
+    async fn sync_pgbouncer(&self, default: &PgPool) -> Result<()> {
+        let databases = registry::list(default).await?;
+        self.0.pgbouncer.sync(&databases).await?;
+
+        Ok(())
     }
 
     /// Get a connection to the default database
@@ -192,60 +450,140 @@ impl Databases {
         Ok(pool)
     }
 
-    /// Ensure the specified database exists and is configured properly
+    /// Ensure the specified database exists, is owned by the user, and is configured properly.
+    /// Databases claimed by one cluster cannot be ensured by another or without a cluster, and roles
+    /// or databases that already exist but are not managed are never taken over, unless the database
+    /// is already owned by the role. Returns whether the database was newly created.
     #[instrument(skip(self, password))]
-    pub async fn ensure(&self, database: &str, password: &str) -> Result<()> {
+    pub async fn ensure(
+        &self,
+        database: &str,
+        username: &str,
+        password: &str,
+        origin: Origin,
+        cluster: Option<&str>,
+        retain: bool,
+    ) -> Result<bool> {
         if database == self.0.default_dbname {
             return Err(Error::DefaultDatabase);
         }
+        if username == self.0.default_username || username == self.0.auth_user {
+            return Err(Error::Unmanaged("role", username.to_string()));
+        }
+        Identifier::new(database)?;
+        Identifier::new(username)?;
 
-        // Setup the database and corresponding user
         let default = self.get_default().await?;
-        ensure_user(database, password, &default).await?;
-        ensure_database(database, &default).await?;
+        let managed = registry::get(database, &default).await?;
+        if let Some(managed) = &managed {
+            check_cluster(managed, cluster)?;
+        }
+
+        let role_exists = role_exists(username, &default).await?;
+        let database_owner = database_owner(database, &default).await?;
+        check_existing(
+            database,
+            username,
+            managed.as_ref(),
+            role_exists,
+            database_owner.as_deref(),
+        )?;
+
+        // Register the database before creating anything, so a partially completed setup can
+        // still be resumed. Ownership is enforced again by the registration itself, in case
+        // another caller claimed the database in the meantime.
+        while !registry::register(database, username, origin, cluster, retain, &default).await? {
+            let managed = registry::get(database, &default).await?;
+            if let Some(managed) = &managed {
+                check_cluster(managed, cluster)?;
+            }
+        }
+
+        // Setup the database and corresponding user
+        ensure_user(username, password, &default).await?;
+        let created = ensure_database(database, username, &default).await?;
         info!("setup database and user");
 
         // Configure the database for authentication
+        let auth_user = Identifier::new(&self.0.auth_user)?;
         let connection = self.get(database).await?;
-        ensure_schema(&connection).await?;
-        ensure_authentication_query(&connection).await?;
+        ensure_schema(&auth_user, &connection).await?;
+        lookup::ensure(self.0.lookup_function, &auth_user, &connection).await?;
 
-        Ok(())
+        self.sync_pgbouncer(&default).await?;
+
+        Ok(created)
+    }
+
+    /// Compare the live state of the database and its owner against the desired state
+    #[instrument(skip(self))]
+    pub async fn drift(&self, database: &str, username: &str) -> Result<Vec<Drift>> {
+        let mut drift = Vec::new();
+
+        let default = self.get_default().await?;
+        if drift::check_server(database, username, &default, &mut drift).await? {
+            let connection = self.get(database).await?;
+            drift::check_database(&self.0.auth_user, &connection, &mut drift).await?;
+        }
+
+        Ok(drift)
     }
 
     /// Remove a database from being managed. If `retain` is true, the database will not be dropped.
+    /// When `retain` is not specified, the value from when the database was registered is used.
+    /// Databases claimed by one cluster cannot be removed by another or without a cluster.
     #[instrument]
-    pub async fn remove(&self, database: &str, retain: bool) -> Result<()> {
+    pub async fn remove(
+        &self,
+        database: &str,
+        retain: Option<bool>,
+        cluster: Option<&str>,
+    ) -> Result<()> {
         if database == self.0.default_dbname {
             return Err(Error::DefaultDatabase);
         }
+        let name = Identifier::new(database)?;
+
+        let default = self.get_default().await?;
+        let managed = registry::get(database, &default).await?;
+        let Some(managed) = managed else {
+            return Err(Error::NotManaged);
+        };
+        check_cluster(&managed, cluster)?;
+        let owner = Identifier::new(&managed.owner)?;
 
-        let Some(pool) = ({
+        let pool = {
             let mut pools = self.0.pools.write();
             pools.remove(database)
-        }) else { return Ok(()) };
-
-        pool.close().await;
-
-        let default = self.get_default().await?;
+        };
+        if let Some(pool) = pool {
+            pool.close().await;
+        }
 
-        let sql = if retain {
-            format!(
-                "ALTER DATABASE {database} OWNER TO {}",
-                &self.0.default_username
-            )
+        // The database and user may not exist if their setup never completed
+        if retain.unwrap_or(managed.retain) {
+            if database_exists(database, &default).await? {
+                let default_owner = Identifier::new(&self.0.default_username)?;
+                query(&format!("ALTER DATABASE {name} OWNER TO {default_owner}"))
+                    .execute(&default)
+                    .await?;
+            }
         } else {
-            format!("DROP DATABASE {database}")
-        };
-        query(&sql).execute(&default).await?;
+            query(&format!("DROP DATABASE IF EXISTS {name}"))
+                .execute(&default)
+                .await?;
+        }
         info!("removed database");
 
         // Remove the user
-        query(&format!("DROP USER {database}"))
+        query(&format!("DROP USER IF EXISTS {owner}"))
             .execute(&default)
             .await?;
         info!("removed user");
 
+        registry::deregister(database, &default).await?;
+        self.sync_pgbouncer(&default).await?;
+
         Ok(())
     }
 }
@@ -275,72 +613,133 @@ async fn ensure_user(name: &str, password: &str, pool: &PgPool) -> Result<()> {
         .await?;
     debug!(?user);
 
+    let password = Literal::new(password)?;
+    let identifier = Identifier::new(name)?;
     let sql = match user {
-        Some(_) => format!("ALTER USER {name} WITH PASSWORD '{password}'"),
-        None => format!("CREATE USER {name} WITH LOGIN NOSUPERUSER NOCREATEROLE NOCREATEDB NOREPLICATION NOBYPASSRLS PASSWORD '{password}'"),
+        Some(_) => format!("ALTER USER {identifier} WITH PASSWORD {password}"),
+        None => format!("CREATE USER {identifier} WITH LOGIN NOSUPERUSER NOCREATEROLE NOCREATEDB NOREPLICATION NOBYPASSRLS PASSWORD {password}"),
     };
     query(&sql).execute(pool).await?;
+    mark_managed(name, pool).await?;
     info!("upserted user");
 
     Ok(())
 }
 
-/// Ensure the database exists
+/// Mark the role as managed so it can be looked up by the managed lookup function
+#[instrument(skip(pool))]
+async fn mark_managed(name: &str, pool: &PgPool) -> Result<()> {
+    let name = Identifier::new(name)?;
+    let comment = Literal::new(lookup::MANAGED_ROLE_COMMENT)?;
+    query(&format!("COMMENT ON ROLE {name} IS {comment}"))
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Ensure the database exists with the correct owner, returning whether it was created
 #[instrument(skip(pool))]
-async fn ensure_database(name: &str, pool: &PgPool) -> Result<()> {
+async fn ensure_database(name: &str, owner: &str, pool: &PgPool) -> Result<bool> {
+    let exists = database_exists(name, pool).await?;
+    debug!(%exists);
+
+    let name = Identifier::new(name)?;
+    let owner = Identifier::new(owner)?;
+
+    // Create the database or ensure it's owner is correct
+    let sql = match exists {
+        true => format!("ALTER DATABASE {name} OWNER TO {owner}"),
+        false => format!("CREATE DATABASE {name} WITH OWNER {owner}"),
+    };
+    query(&sql).execute(pool).await?;
+
+    Ok(!exists)
+}
+
+/// Check whether the role exists
+async fn role_exists(name: &str, pool: &PgPool) -> Result<bool> {
+    let role = query!(
+        "SELECT oid FROM pg_catalog.pg_roles WHERE rolname = $1",
+        name
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(role.is_some())
+}
+
+/// Check whether the database exists
+async fn database_exists(name: &str, pool: &PgPool) -> Result<bool> {
     let database = query!(
         "SELECT oid FROM pg_catalog.pg_database WHERE datname = $1",
         name
     )
     .fetch_optional(pool)
     .await?;
-    debug!(exists = ?database.is_some());
-
-    // Create the database or ensure it's owner is correct
-    let sql = match database {
-        Some(_) => format!("ALTER DATABASE {name} OWNER TO {name}"),
-        None => format!("CREATE DATABASE {name} WITH OWNER {name}"),
-    };
-    query(&sql).execute(pool).await?;
 
-    Ok(())
+    Ok(database.is_some())
 }
 
-/// Ensure the pgbouncer schema exists and has the proper permissions
-#[instrument(skip_all)]
-async fn ensure_schema(pool: &PgPool) -> Result<()> {
-    query!("CREATE SCHEMA IF NOT EXISTS pgbouncer")
-        .execute(pool)
-        .await?;
+/// Get the owner of the database, if it exists
+async fn database_owner(name: &str, pool: &PgPool) -> Result<Option<String>> {
+    let database = query!(
+        r#"SELECT pg_catalog.pg_get_userbyid(datdba) AS "owner!" FROM pg_catalog.pg_database WHERE datname = $1"#,
+        name
+    )
+    .fetch_optional(pool)
+    .await?;
 
-    query!("GRANT USAGE ON SCHEMA pgbouncer TO pgbouncer")
-        .execute(pool)
-        .await?;
+    Ok(database.map(|database| database.owner))
+}
+
+/// Ensure the role and database can be used for the database being ensured. Only roles and
+/// databases that were created for it can be reused, except for a database from before the
+/// registry existed, which is adopted when it is owned by the requested role.
+fn check_existing(
+    database: &str,
+    username: &str,
+    managed: Option<&ManagedDatabase>,
+    role_exists: bool,
+    database_owner: Option<&str>,
+) -> Result<()> {
+    let adopted = managed.is_none() && database_owner == Some(username);
+    let owned = adopted || managed.is_some_and(|managed| managed.owner == username);
+
+    if role_exists && !owned {
+        return Err(Error::Unmanaged("role", username.to_string()));
+    }
+    if managed.is_none() && database_owner.is_some() && !adopted {
+        return Err(Error::Unmanaged("database", database.to_string()));
+    }
 
     Ok(())
 }
 
-/// Ensure the authentication lookup function exists and has the proper permissions
-#[instrument(skip_all)]
-async fn ensure_authentication_query(pool: &PgPool) -> Result<()> {
-    let user_lookup_function = query_file!("queries/authentication-query-exists.sql")
-        .fetch_optional(pool)
-        .await?;
-    debug!(exists = ?user_lookup_function.is_some());
-    if user_lookup_function.is_none() {
-        query_file!("queries/authentication-query-function.sql")
-            .execute(pool)
-            .await?;
+/// Ensure the caller may manage the database. Databases claimed by a cluster can only be managed by
+/// that cluster, and databases created through the API only without a cluster.
+fn check_cluster(managed: &ManagedDatabase, cluster: Option<&str>) -> Result<()> {
+    match (managed.cluster.as_deref(), cluster) {
+        (Some(owner), Some(cluster)) if owner == cluster => Ok(()),
+        (Some(owner), _) => Err(Error::ClaimedByCluster(owner.to_string())),
+        (None, None) if managed.origin == Origin::Api => Ok(()),
+        (None, None) => Err(Error::ClusterRequired),
+        // Registered by the operator before clusters were recorded, so the cluster adopts it
+        (None, Some(_)) if managed.origin == Origin::Operator => Ok(()),
+        (None, Some(_)) => Err(Error::ManagedByApi),
     }
-    info!("created authentication lookup if not exists");
+}
 
-    query!("REVOKE ALL ON FUNCTION pgbouncer.user_lookup(text) FROM public, pgbouncer")
+/// Ensure the pgbouncer schema exists and has the proper permissions
+#[instrument(skip_all)]
+async fn ensure_schema(auth_user: &Identifier<'_>, pool: &PgPool) -> Result<()> {
+    query!("CREATE SCHEMA IF NOT EXISTS pgbouncer")
         .execute(pool)
         .await?;
-    query!("GRANT EXECUTE ON FUNCTION pgbouncer.user_lookup(text) TO pgbouncer")
+
+    query(&format!("GRANT USAGE ON SCHEMA pgbouncer TO {auth_user}"))
         .execute(pool)
         .await?;
-    info!("updated lookup function permissions");
 
     Ok(())
 }
@@ -353,6 +752,139 @@ pub enum Error {
     InvalidPermissions,
     #[error("cannot create or remove default database")]
     DefaultDatabase,
+    #[error("database is not managed")]
+    NotManaged,
+    #[error("database is managed by the {0:?} cluster")]
+    ClaimedByCluster(String),
+    #[error("database is managed by the operator, the cluster that owns it must be specified")]
+    ClusterRequired,
+    #[error("database is managed through the api rather than by a cluster")]
+    ManagedByApi,
+    #[error("role {0:?} already owns another managed database")]
+    RoleInUse(String),
+    #[error("{0} {1:?} already exists and is not managed by external-postgres")]
+    Unmanaged(&'static str, String),
+    #[error(transparent)]
+    InvalidName(#[from] quote::Error),
+    #[error(transparent)]
+    PgBouncer(#[from] pgbouncer::Error),
     #[error(transparent)]
     Internal(#[from] sqlx::Error),
 }
+
+impl Error {
+    /// Whether retrying the operation could succeed without any intervention
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::InvalidPermissions
+            | Self::DefaultDatabase
+            | Self::NotManaged
+            | Self::ClaimedByCluster(_)
+            | Self::ClusterRequired
+            | Self::ManagedByApi
+            | Self::RoleInUse(_)
+            | Self::Unmanaged(..) => false,
+            Self::InvalidName(_) => false,
+            Self::PgBouncer(error) => error.is_transient(),
+            Self::Internal(sqlx::Error::Database(error)) => {
+                // Connection exceptions, transaction rollbacks, insufficient resources, operator
+                // intervention, and system errors
+                let code = error.code().unwrap_or_default();
+                ["08", "40", "53", "57", "58"]
+                    .iter()
+                    .any(|class| code.starts_with(class))
+            }
+            Self::Internal(
+                sqlx::Error::Io(_)
+                | sqlx::Error::Tls(_)
+                | sqlx::Error::Protocol(_)
+                | sqlx::Error::PoolTimedOut
+                | sqlx::Error::PoolClosed
+                | sqlx::Error::WorkerCrashed,
+            ) => true,
+            Self::Internal(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_existing, Error};
+    use crate::models::database::{ManagedDatabase, Origin};
+    use chrono::Utc;
+
+    fn managed(owner: &str) -> ManagedDatabase {
+        ManagedDatabase {
+            name: String::from("app"),
+            owner: owner.to_string(),
+            origin: Origin::Operator,
+            cluster: Some(String::from("default")),
+            retain: false,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn unmanaged(result: super::Result<()>) -> Option<(&'static str, String)> {
+        match result {
+            Err(Error::Unmanaged(kind, name)) => Some((kind, name)),
+            Ok(()) => None,
+            Err(error) => panic!("unexpected error: {error}"),
+        }
+    }
+
+    #[test]
+    fn new_database_and_role() {
+        assert!(check_existing("app", "app", None, false, None).is_ok());
+    }
+
+    #[test]
+    fn registered_database_is_reused() {
+        let managed = managed("app");
+        assert!(check_existing("app", "app", Some(&managed), true, Some("app")).is_ok());
+    }
+
+    #[test]
+    fn partially_provisioned_database_is_resumed() {
+        let managed = managed("app");
+        assert!(check_existing("app", "app", Some(&managed), false, None).is_ok());
+        assert!(check_existing("app", "app", Some(&managed), true, None).is_ok());
+    }
+
+    #[test]
+    fn database_from_before_registry_is_adopted() {
+        // Provisioned before the registry existed, so it was never registered
+        assert!(check_existing("app", "app", None, true, Some("app")).is_ok());
+    }
+
+    #[test]
+    fn database_owned_by_another_role_is_not_adopted() {
+        assert_eq!(
+            unmanaged(check_existing("app", "app", None, true, Some("someone"))),
+            Some(("role", String::from("app")))
+        );
+        assert_eq!(
+            unmanaged(check_existing("app", "app", None, false, Some("someone"))),
+            Some(("database", String::from("app")))
+        );
+    }
+
+    #[test]
+    fn unrelated_existing_role_is_not_taken_over() {
+        assert_eq!(
+            unmanaged(check_existing("app", "admin", None, true, None)),
+            Some(("role", String::from("admin")))
+        );
+
+        let managed = managed("app");
+        assert_eq!(
+            unmanaged(check_existing(
+                "app",
+                "admin",
+                Some(&managed),
+                true,
+                Some("app")
+            )),
+            Some(("role", String::from("admin")))
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use super::Result;
+use sqlx::{postgres::PgPool, query};
+use std::fmt::{self, Display, Formatter};
+use tracing::instrument;
+
+/// A difference between the desired and live state of a managed database
+#[derive(Debug, Eq, PartialEq)]
+pub enum Drift {
+    /// The role that owns the database does not exist
+    RoleMissing,
+    /// The role that owns the database cannot login
+    RoleCannotLogin,
+    /// The database does not exist
+    DatabaseMissing,
+    /// The database is owned by a different role
+    WrongOwner(String),
+    /// The pgbouncer schema does not exist in the database
+    SchemaMissing,
+    /// The auth user cannot use the pgbouncer schema
+    SchemaUsageRevoked,
+    /// The lookup function does not exist in the database
+    LookupFunctionMissing,
+    /// The auth user cannot execute the lookup function
+    LookupFunctionExecuteRevoked,
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RoleMissing => write!(f, "role does not exist"),
+            Self::RoleCannotLogin => write!(f, "role cannot login"),
+            Self::DatabaseMissing => write!(f, "database does not exist"),
+            Self::WrongOwner(owner) => write!(f, "database is owned by {owner:?}"),
+            Self::SchemaMissing => write!(f, "pgbouncer schema does not exist"),
+            Self::SchemaUsageRevoked => write!(f, "auth user cannot use the pgbouncer schema"),
+            Self::LookupFunctionMissing => write!(f, "lookup function does not exist"),
+            Self::LookupFunctionExecuteRevoked => {
+                write!(f, "auth user cannot execute the lookup function")
+            }
+        }
+    }
+}
+
+/// Check the role and database from the server's perspective, returning whether the database
+/// exists so its contents can be checked
+#[instrument(skip(pool, drift))]
+pub(super) async fn check_server(
+    database: &str,
+    username: &str,
+    pool: &PgPool,
+    drift: &mut Vec<Drift>,
+) -> Result<bool> {
+    let role = query!(
+        r#"SELECT rolcanlogin as "can_login!" FROM pg_catalog.pg_roles WHERE rolname = $1"#,
+        username
+    )
+    .fetch_optional(pool)
+    .await?;
+    match role {
+        None => drift.push(Drift::RoleMissing),
+        Some(role) if !role.can_login => drift.push(Drift::RoleCannotLogin),
+        Some(_) => {}
+    }
+
+    let owner = query!(
+        r#"SELECT pg_catalog.pg_get_userbyid(datdba) as "owner!" FROM pg_catalog.pg_database WHERE datname = $1"#,
+        database
+    )
+    .fetch_optional(pool)
+    .await?;
+    match owner {
+        None => {
+            drift.push(Drift::DatabaseMissing);
+            return Ok(false);
+        }
+        Some(row) if row.owner != username => drift.push(Drift::WrongOwner(row.owner)),
+        Some(_) => {}
+    }
+
+    Ok(true)
+}
+
+/// Check the pgbouncer schema and lookup function within the database
+#[instrument(skip(pool, drift))]
+pub(super) async fn check_database(
+    auth_user: &str,
+    pool: &PgPool,
+    drift: &mut Vec<Drift>,
+) -> Result<()> {
+    let schema = query!(
+        r#"SELECT pg_catalog.has_schema_privilege($1, oid, 'USAGE') as "usage!" FROM pg_catalog.pg_namespace WHERE nspname = 'pgbouncer'"#,
+        auth_user
+    )
+    .fetch_optional(pool)
+    .await?;
+    match schema {
+        None => {
+            drift.push(Drift::SchemaMissing);
+            return Ok(());
+        }
+        Some(schema) if !schema.usage => drift.push(Drift::SchemaUsageRevoked),
+        Some(_) => {}
+    }
+
+    let function = query!(
+        r#"SELECT pg_catalog.has_function_privilege($1, p.oid, 'EXECUTE') as "execute!"
+            FROM pg_catalog.pg_proc p
+            INNER JOIN pg_catalog.pg_namespace n ON p.pronamespace = n.oid
+            WHERE p.proname = 'user_lookup' AND n.nspname = 'pgbouncer'"#,
+        auth_user
+    )
+    .fetch_optional(pool)
+    .await?;
+    match function {
+        None => drift.push(Drift::LookupFunctionMissing),
+        Some(function) if !function.execute => drift.push(Drift::LookupFunctionExecuteRevoked),
+        Some(_) => {}
+    }
+
+    Ok(())
+}
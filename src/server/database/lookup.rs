@@ -0,0 +1,140 @@
+use super::{quote::Identifier, Result};
+use clap::ValueEnum;
+use sqlx::{postgres::PgPool, query, query_file};
+use std::fmt::{self, Display, Formatter};
+use tracing::{debug, info, instrument, warn};
+
+/// The version of the lookup functions defined in `queries/authentication-query-function*.sql`.
+/// Increment this whenever a function changes so existing databases get upgraded.
+pub const VERSION: u32 = 1;
+
+/// The prefix of the comment stamped on the lookup function to record its version
+const STAMP_PREFIX: &str = "external-postgres user_lookup v";
+
+/// The comment used to mark roles as managed for the [`Variant::Managed`] lookup function
+pub(super) const MANAGED_ROLE_COMMENT: &str = "managed by external-postgres";
+
+/// Which lookup function to install
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum Variant {
+    /// Return the password hash of any role
+    #[default]
+    Permissive,
+    /// Refuse superusers, roles that cannot login, and roles with expired passwords
+    Hardened,
+    /// Like hardened, but only allow roles managed by external-postgres
+    Managed,
+}
+
+impl Variant {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Permissive => "permissive",
+            Self::Hardened => "hardened",
+            Self::Managed => "managed",
+        }
+    }
+}
+
+impl Display for Variant {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The version and variant of an installed lookup function
+#[derive(Debug)]
+pub(super) struct Installed {
+    pub version: u32,
+    /// The name of the variant, which may be unknown to this version of the server
+    pub variant: String,
+}
+
+/// Get the version of the lookup function installed in the database. Functions installed before
+/// versioning was introduced are reported as version 0, and functions installed before variants
+/// were introduced as permissive.
+#[instrument(skip_all)]
+pub(super) async fn installed(pool: &PgPool) -> Result<Option<Installed>> {
+    let function = query_file!("queries/authentication-query-exists.sql")
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(function.map(|function| {
+        let stamp = function
+            .description
+            .as_deref()
+            .and_then(|description| description.strip_prefix(STAMP_PREFIX))
+            .unwrap_or_default();
+        let (version, variant) = stamp.split_once(' ').unwrap_or((stamp, ""));
+
+        Installed {
+            version: version.parse().unwrap_or_default(),
+            variant: match variant {
+                "" => Variant::Permissive.to_string(),
+                variant => variant.to_string(),
+            },
+        }
+    }))
+}
+
+/// Ensure the latest version of the lookup function variant is installed and has the proper
+/// permissions
+#[instrument(skip(auth_user, pool))]
+pub(super) async fn ensure(
+    variant: Variant,
+    auth_user: &Identifier<'_>,
+    pool: &PgPool,
+) -> Result<()> {
+    let installed = installed(pool).await?;
+    debug!(?installed);
+
+    match &installed {
+        Some(installed) if installed.version > VERSION => {
+            warn!(%installed.version, "lookup function is newer than supported, leaving as-is")
+        }
+        Some(installed)
+            if installed.version == VERSION && installed.variant == variant.as_str() =>
+        {
+            debug!("lookup function is up-to-date")
+        }
+        _ => {
+            match variant {
+                Variant::Permissive => {
+                    query_file!("queries/authentication-query-function.sql")
+                        .execute(pool)
+                        .await?
+                }
+                Variant::Hardened => {
+                    query_file!("queries/authentication-query-function-hardened.sql")
+                        .execute(pool)
+                        .await?
+                }
+                Variant::Managed => {
+                    query_file!("queries/authentication-query-function-managed.sql")
+                        .execute(pool)
+                        .await?
+                }
+            };
+            query(&format!(
+                "COMMENT ON FUNCTION pgbouncer.user_lookup(text) IS '{STAMP_PREFIX}{VERSION} {variant}'"
+            ))
+            .execute(pool)
+            .await?;
+            info!(from = ?installed, to = %VERSION, "installed lookup function");
+        }
+    }
+
+    query(&format!(
+        "REVOKE ALL ON FUNCTION pgbouncer.user_lookup(text) FROM public, {auth_user}"
+    ))
+    .execute(pool)
+    .await?;
+    query(&format!(
+        "GRANT EXECUTE ON FUNCTION pgbouncer.user_lookup(text) TO {auth_user}"
+    ))
+    .execute(pool)
+    .await?;
+    info!("updated lookup function permissions");
+
+    Ok(())
+}
@@ -0,0 +1,371 @@
+use super::{quote::Identifier, Options as DatabaseOptions};
+use crate::models::database::ManagedDatabase;
+use clap::Args;
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use sqlx::{
+    postgres::{PgConnectOptions, PgConnection},
+    ConnectOptions, Connection, Executor,
+};
+use std::{
+    fmt::{self, Debug, Display, Formatter, Write},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use tokio::{fs, io::AsyncWriteExt};
+use tracing::{debug, info, instrument};
+
+mod userlist;
+
+/// The length of the generated password for the auth user
+const AUTH_PASSWORD_LENGTH: usize = 48;
+
+#[derive(Debug, Args)]
+#[group(skip)]
+pub struct Options {
+    /// The path to write the PgBouncer include file containing the managed databases to
+    #[arg(long = "pgbouncer-include-path", env = "PGBOUNCER_INCLUDE_PATH")]
+    pub include_path: Option<PathBuf>,
+
+    /// The host for PgBouncer to connect to the databases with, defaults to the database host
+    #[arg(long = "pgbouncer-database-host", env = "PGBOUNCER_DATABASE_HOST")]
+    pub database_host: Option<String>,
+
+    /// The port for PgBouncer to connect to the databases with, defaults to the database port
+    #[arg(long = "pgbouncer-database-port", env = "PGBOUNCER_DATABASE_PORT")]
+    pub database_port: Option<u16>,
+
+    /// The connection URL for the PgBouncer admin console. When set, PgBouncer is reloaded
+    /// whenever the include file changes.
+    #[arg(long = "pgbouncer-admin-url", env = "PGBOUNCER_ADMIN_URL")]
+    pub admin_url: Option<String>,
+
+    /// The path to write the credentials for the auth user to, in the `auth_file` format. The
+    /// password is reused from this file across restarts.
+    #[arg(long = "pgbouncer-auth-file", env = "PGBOUNCER_AUTH_FILE")]
+    pub auth_file: Option<PathBuf>,
+
+    /// How often to rotate the password for the auth user
+    #[arg(
+        long = "pgbouncer-auth-rotation-interval",
+        env = "PGBOUNCER_AUTH_ROTATION_INTERVAL",
+        value_parser = humantime::parse_duration
+    )]
+    pub auth_rotation_interval: Option<Duration>,
+}
+
+/// The credentials PgBouncer uses to authenticate with the database when looking up users
+#[derive(Clone, Eq, PartialEq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    /// Generate new credentials for the user
+    pub fn generate(username: &str) -> Self {
+        let password = OsRng
+            .sample_iter(&Alphanumeric)
+            .take(AUTH_PASSWORD_LENGTH)
+            .map(char::from)
+            .collect();
+
+        Self {
+            username: username.to_string(),
+            password,
+        }
+    }
+
+    /// Render the credentials in PgBouncer's `auth_file` format
+    pub fn userlist(&self) -> String {
+        userlist::render(self)
+    }
+}
+
+impl Debug for Credentials {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Generates the `[databases]` section of the PgBouncer configuration for the managed databases
+#[derive(Debug)]
+pub(super) struct PgBouncer {
+    include_path: Option<PathBuf>,
+    auth_file: Option<PathBuf>,
+    auth_user: String,
+    host: String,
+    port: u16,
+    admin: Option<PgConnectOptions>,
+    /// Set while a reload has not succeeded since the files were last written
+    reload_pending: AtomicBool,
+}
+
+impl PgBouncer {
+    pub fn new(opts: &Options, database: &DatabaseOptions) -> Result<Self> {
+        let host = opts
+            .database_host
+            .clone()
+            .or_else(|| database.host.clone().filter(|host| !host.is_empty()))
+            .unwrap_or_else(|| database.socket.to_string_lossy().to_string());
+
+        let admin = match &opts.admin_url {
+            Some(url) => {
+                // The admin console rejects startup parameters it does not know about
+                let mut options = PgConnectOptions::from_str(url)
+                    .map_err(Error::InvalidAdminUrl)?
+                    .extra_float_digits(None);
+                options.disable_statement_logging();
+                Some(options)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            include_path: opts.include_path.clone(),
+            auth_file: opts.auth_file.clone(),
+            auth_user: database.auth_user.clone(),
+            host,
+            port: opts.database_port.unwrap_or(database.port),
+            admin,
+            reload_pending: AtomicBool::new(false),
+        })
+    }
+
+    /// Write the include file for the managed databases, reloading PgBouncer if it changed or the
+    /// previous reload failed
+    #[instrument(skip_all)]
+    pub async fn sync(&self, databases: &[ManagedDatabase]) -> Result<()> {
+        let Some(path) = &self.include_path else {
+            return Ok(());
+        };
+
+        let contents = self.render(databases)?;
+        if write_if_changed(path, &contents, 0o644).await? {
+            info!(path = %path.display(), count = databases.len(), "wrote pgbouncer include file");
+        } else if !self.reload_pending() {
+            debug!("pgbouncer include file is up-to-date");
+            return Ok(());
+        }
+
+        self.reload().await
+    }
+
+    /// Read the previously stored password for the auth user from the auth file
+    #[instrument(skip_all)]
+    pub async fn stored_credentials(&self) -> Result<Option<Credentials>> {
+        let Some(path) = &self.auth_file else {
+            return Ok(None);
+        };
+
+        let contents = match fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(
+            userlist::find(&contents, &self.auth_user).map(|password| Credentials {
+                username: self.auth_user.clone(),
+                password,
+            }),
+        )
+    }
+
+    /// Write the credentials for the auth user to the auth file, reloading PgBouncer if they changed
+    /// or the previous reload failed
+    #[instrument(skip_all)]
+    pub async fn write_credentials(&self, credentials: &Credentials) -> Result<()> {
+        let Some(path) = &self.auth_file else {
+            return Ok(());
+        };
+
+        if write_if_changed(path, &credentials.userlist(), 0o600).await? {
+            info!(path = %path.display(), "wrote pgbouncer auth file");
+        } else if !self.reload_pending() {
+            debug!("pgbouncer auth file is up-to-date");
+            return Ok(());
+        }
+
+        self.reload().await
+    }
+
+    /// Render the include file for the managed databases
+    fn render(&self, databases: &[ManagedDatabase]) -> Result<String> {
+        let mut contents = String::from(
+            "; This file is managed by external-postgres, any changes will be overwritten\n[databases]\n",
+        );
+
+        for database in databases {
+            let name = Identifier::new(&database.name)?;
+            let key: &dyn Display = match is_bare_name(&database.name) {
+                true => &database.name,
+                false => &name,
+            };
+
+            writeln!(
+                contents,
+                "{key} = host={} port={} dbname={} auth_user={}",
+                Value(&self.host),
+                self.port,
+                Value(&database.name),
+                Value(&self.auth_user),
+            )
+            .expect("writing to a string cannot fail");
+        }
+
+        Ok(contents)
+    }
+
+    /// Whether a previous reload failed, in which case PgBouncer may still be using stale files
+    fn reload_pending(&self) -> bool {
+        self.reload_pending.load(Ordering::Acquire)
+    }
+
+    /// Instruct PgBouncer to reload its configuration through the admin console
+    #[instrument(skip_all)]
+    async fn reload(&self) -> Result<()> {
+        let Some(options) = &self.admin else {
+            return Ok(());
+        };
+
+        // Stays set until a reload succeeds, so a failure is retried on the next sync even if the
+        // files do not change again
+        self.reload_pending.store(true, Ordering::Release);
+
+        // The admin console only supports the simple query protocol, which is used for queries
+        // without any arguments
+        let mut connection = PgConnection::connect_with(options)
+            .await
+            .map_err(Error::Reload)?;
+        connection.execute("RELOAD").await.map_err(Error::Reload)?;
+        connection.close().await.map_err(Error::Reload)?;
+        self.reload_pending.store(false, Ordering::Release);
+        info!("reloaded pgbouncer");
+
+        Ok(())
+    }
+}
+
+/// Write the contents to the file if they differ from what it currently contains, returning
+/// whether the file was written
+async fn write_if_changed(path: &Path, contents: &str, mode: u32) -> Result<bool> {
+    match fs::read_to_string(path).await {
+        Ok(existing) if existing == contents => return Ok(false),
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    // Write to a temporary file first so PgBouncer never reads a partially written file. The name
+    // is unique so concurrent writes to files with the same stem cannot clobber each other.
+    let temporary = temporary_path(path);
+    let result = async {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(mode)
+            .open(&temporary)
+            .await?;
+        file.write_all(contents.as_bytes()).await?;
+        file.sync_all().await?;
+        fs::rename(&temporary, path).await
+    }
+    .await;
+
+    if let Err(error) = result {
+        let _ = fs::remove_file(&temporary).await;
+        return Err(error.into());
+    }
+
+    Ok(true)
+}
+
+/// Get a unique path to write the file's new contents to before moving it into place
+fn temporary_path(path: &Path) -> PathBuf {
+    let suffix = OsRng
+        .sample_iter(&Alphanumeric)
+        .take(8)
+        .map(char::from)
+        .collect::<String>();
+
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{suffix}.tmp"));
+    path.with_file_name(name)
+}
+
+/// Whether the database name can be used in the configuration without quoting
+fn is_bare_name(name: &str) -> bool {
+    name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// A connection string value, quoted when it contains characters PgBouncer would misinterpret
+struct Value<'s>(&'s str);
+
+impl Display for Value<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let needs_quoting = self.0.is_empty()
+            || self
+                .0
+                .chars()
+                .any(|c| c.is_whitespace() || c == '\'' || c == '\\');
+
+        match needs_quoting {
+            true => write!(f, "'{}'", self.0.replace('\'', "''")),
+            false => f.write_str(self.0),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to write pgbouncer configuration: {0}")]
+    Write(#[from] std::io::Error),
+    #[error("invalid pgbouncer admin url: {0}")]
+    InvalidAdminUrl(#[source] sqlx::Error),
+    #[error("failed to reload pgbouncer: {0}")]
+    Reload(#[source] sqlx::Error),
+    #[error(transparent)]
+    InvalidName(#[from] super::quote::Error),
+}
+
+impl Error {
+    /// Whether retrying the operation could succeed without any intervention
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Write(_) | Self::Reload(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::temporary_path;
+    use std::path::Path;
+
+    #[test]
+    fn temporary_path_keeps_file_name() {
+        let path = temporary_path(Path::new("/etc/pgbouncer/pgbouncer.ini"));
+        let name = path.file_name().unwrap().to_string_lossy();
+
+        assert_eq!(path.parent(), Some(Path::new("/etc/pgbouncer")));
+        assert!(name.starts_with("pgbouncer.ini."));
+        assert!(name.ends_with(".tmp"));
+    }
+
+    #[test]
+    fn temporary_path_is_unique() {
+        let ini = temporary_path(Path::new("/etc/pgbouncer/pgbouncer.ini"));
+        let txt = temporary_path(Path::new("/etc/pgbouncer/pgbouncer.txt"));
+        assert_ne!(ini, txt);
+
+        let first = temporary_path(Path::new("/etc/pgbouncer/pgbouncer.ini"));
+        let second = temporary_path(Path::new("/etc/pgbouncer/pgbouncer.ini"));
+        assert_ne!(first, second);
+    }
+}
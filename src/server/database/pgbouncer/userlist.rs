@@ -0,0 +1,125 @@
+//! Reading and writing PgBouncer's `auth_file` format, where each line contains a double-quoted
+//! username and password
+
+use super::Credentials;
+
+/// Render the credentials in the auth file format
+pub fn render(credentials: &Credentials) -> String {
+    format!(
+        "{} {}\n",
+        quote(&credentials.username),
+        quote(&credentials.password)
+    )
+}
+
+/// Find the password for the user in the contents of an auth file
+pub fn find(contents: &str, username: &str) -> Option<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with(';'))
+        .filter_map(parse_line)
+        .find(|(user, _)| user == username)
+        .map(|(_, password)| password)
+}
+
+/// Quote a value, doubling any embedded quotes
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+/// Parse a line into its username and password
+fn parse_line(line: &str) -> Option<(String, String)> {
+    let (username, rest) = unquote(line)?;
+    let (password, _) = unquote(rest.trim_start())?;
+    Some((username, password))
+}
+
+/// Read a quoted value from the start of the input, returning it and the remaining input
+fn unquote(input: &str) -> Option<(String, &str)> {
+    let mut chars = input.strip_prefix('"')?.char_indices().peekable();
+    let mut value = String::new();
+
+    while let Some((i, c)) = chars.next() {
+        if c != '"' {
+            value.push(c);
+        } else if matches!(chars.peek(), Some((_, '"'))) {
+            value.push('"');
+            chars.next();
+        } else {
+            // Account for the opening quote that was stripped
+            return Some((value, &input[i + 2..]));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, render, Credentials};
+
+    fn credentials(username: &str, password: &str) -> Credentials {
+        Credentials {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    #[test]
+    fn render_plain() {
+        let rendered = render(&credentials("pgbouncer", "secret"));
+        assert_eq!(rendered, "\"pgbouncer\" \"secret\"\n");
+    }
+
+    #[test]
+    fn render_escapes_quotes() {
+        let rendered = render(&credentials("pg\"bouncer", "a\"b\"\"c"));
+        assert_eq!(rendered, "\"pg\"\"bouncer\" \"a\"\"b\"\"\"\"c\"\n");
+    }
+
+    #[test]
+    fn find_user() {
+        let contents = "\"app\" \"one\"\n\"pgbouncer\" \"two\"\n";
+        assert_eq!(find(contents, "pgbouncer").as_deref(), Some("two"));
+        assert_eq!(find(contents, "app").as_deref(), Some("one"));
+    }
+
+    #[test]
+    fn find_missing_user() {
+        assert_eq!(find("\"app\" \"one\"\n", "pgbouncer"), None);
+        assert_eq!(find("", "pgbouncer"), None);
+    }
+
+    #[test]
+    fn find_skips_comments_and_blank_lines() {
+        let contents = "; \"pgbouncer\" \"commented\"\n\n   \n  \"pgbouncer\" \"actual\"  \n";
+        assert_eq!(find(contents, "pgbouncer").as_deref(), Some("actual"));
+    }
+
+    #[test]
+    fn find_skips_malformed_lines() {
+        let contents = "pgbouncer secret\n\"pgbouncer\" \"unterminated\n\"pgbouncer\"\n";
+        assert_eq!(find(contents, "pgbouncer"), None);
+    }
+
+    #[test]
+    fn find_ignores_trailing_fields() {
+        let contents = "\"pgbouncer\"   \"secret\" \"extra\"\n";
+        assert_eq!(find(contents, "pgbouncer").as_deref(), Some("secret"));
+    }
+
+    #[test]
+    fn round_trip() {
+        for password in [
+            "plain",
+            "with \"quotes\"",
+            "\"",
+            "spaces and ; semicolons",
+            "",
+        ] {
+            let rendered = render(&credentials("pgbouncer", password));
+            assert_eq!(find(&rendered, "pgbouncer").as_deref(), Some(password));
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::fmt::{Display, Formatter, Write};
+
+/// The maximum length of an identifier in bytes, anything longer gets silently truncated by PostgreSQL
+const MAX_IDENTIFIER_LENGTH: usize = 63;
+
+/// An identifier (database, role, schema, etc) that is safe to interpolate into a statement
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Identifier<'s>(&'s str);
+
+impl<'s> Identifier<'s> {
+    /// Validate the identifier
+    pub fn new(raw: &'s str) -> Result<Self> {
+        if raw.is_empty() {
+            return Err(Error::EmptyIdentifier);
+        }
+        if raw.len() > MAX_IDENTIFIER_LENGTH {
+            return Err(Error::IdentifierTooLong(raw.to_string()));
+        }
+        if raw.contains('\0') {
+            return Err(Error::NulByte);
+        }
+
+        Ok(Self(raw))
+    }
+}
+
+impl<'s> Display for Identifier<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_char('"')?;
+        for c in self.0.chars() {
+            if c == '"' {
+                f.write_char('"')?;
+            }
+            f.write_char(c)?;
+        }
+        f.write_char('"')
+    }
+}
+
+/// A string literal (i.e. a password) that is safe to interpolate into a statement
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Literal<'s>(&'s str);
+
+impl<'s> Literal<'s> {
+    /// Validate the literal
+    pub fn new(raw: &'s str) -> Result<Self> {
+        if raw.contains('\0') {
+            return Err(Error::NulByte);
+        }
+
+        Ok(Self(raw))
+    }
+}
+
+impl<'s> Display for Literal<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // Use an escape string when backslashes are present so the output does not depend on
+        // the value of `standard_conforming_strings`, the same as libpq's PQescapeLiteral
+        if self.0.contains('\\') {
+            f.write_char('E')?;
+        }
+
+        f.write_char('\'')?;
+        for c in self.0.chars() {
+            if c == '\'' || c == '\\' {
+                f.write_char(c)?;
+            }
+            f.write_char(c)?;
+        }
+        f.write_char('\'')
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("identifier cannot be empty")]
+    EmptyIdentifier,
+    #[error("identifier {0:?} is longer than 63 bytes")]
+    IdentifierTooLong(String),
+    #[error("value cannot contain a nul byte")]
+    NulByte,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Identifier, Literal};
+
+    fn identifier(raw: &str) -> String {
+        Identifier::new(raw).unwrap().to_string()
+    }
+
+    fn literal(raw: &str) -> String {
+        Literal::new(raw).unwrap().to_string()
+    }
+
+    #[test]
+    fn identifier_plain() {
+        assert_eq!(identifier("app"), r#""app""#);
+        assert_eq!(identifier("App"), r#""App""#);
+    }
+
+    #[test]
+    fn identifier_kubernetes_names() {
+        assert_eq!(identifier("my-app"), r#""my-app""#);
+        assert_eq!(identifier("my.app.v2"), r#""my.app.v2""#);
+        assert_eq!(identifier("0-leading-digit"), r#""0-leading-digit""#);
+    }
+
+    #[test]
+    fn identifier_reserved_words() {
+        assert_eq!(identifier("user"), r#""user""#);
+        assert_eq!(identifier("select"), r#""select""#);
+    }
+
+    #[test]
+    fn identifier_hostile() {
+        assert_eq!(identifier(r#"a"b"#), r#""a""b""#);
+        assert_eq!(
+            identifier(r#"x"; DROP DATABASE postgres; --"#),
+            r#""x""; DROP DATABASE postgres; --""#
+        );
+        assert_eq!(identifier(r#"""#), r#""""""#);
+        assert_eq!(identifier("a'b"), r#""a'b""#);
+        assert_eq!(identifier(r"a\b"), r#""a\b""#);
+        assert_eq!(identifier("with space"), r#""with space""#);
+        assert_eq!(identifier("bäckerei"), r#""bäckerei""#);
+    }
+
+    #[test]
+    fn identifier_invalid() {
+        assert!(matches!(Identifier::new(""), Err(Error::EmptyIdentifier)));
+        assert!(matches!(Identifier::new("a\0b"), Err(Error::NulByte)));
+        assert!(matches!(
+            Identifier::new(&"a".repeat(64)),
+            Err(Error::IdentifierTooLong(_))
+        ));
+        assert!(Identifier::new(&"a".repeat(63)).is_ok());
+
+        // Length is measured in bytes, not characters
+        assert!(matches!(
+            Identifier::new(&"ä".repeat(32)),
+            Err(Error::IdentifierTooLong(_))
+        ));
+    }
+
+    #[test]
+    fn literal_plain() {
+        assert_eq!(literal("hunter2"), "'hunter2'");
+        assert_eq!(literal(""), "''");
+    }
+
+    #[test]
+    fn literal_hostile() {
+        assert_eq!(literal("it's"), "'it''s'");
+        assert_eq!(literal("'"), "''''");
+        assert_eq!(
+            literal("'; ALTER USER postgres PASSWORD 'owned"),
+            "'''; ALTER USER postgres PASSWORD ''owned'"
+        );
+        assert_eq!(literal(r#"p"w"#), r#"'p"w'"#);
+        assert_eq!(literal("$$dollar$$"), "'$$dollar$$'");
+        assert_eq!(literal("new\nline"), "'new\nline'");
+    }
+
+    #[test]
+    fn literal_backslashes() {
+        assert_eq!(literal(r"a\b"), r"E'a\\b'");
+        assert_eq!(literal(r"\'"), r"E'\\'''");
+        assert_eq!(literal(r"trailing\"), r"E'trailing\\'");
+    }
+
+    #[test]
+    fn literal_invalid() {
+        assert!(matches!(Literal::new("a\0b"), Err(Error::NulByte)));
+    }
+}
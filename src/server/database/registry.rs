@@ -0,0 +1,227 @@
+use super::{Error, Result};
+use crate::models::database::{ManagedDatabase, Origin};
+use chrono::{DateTime, Utc};
+use rand::{rngs::OsRng, RngCore};
+use sqlx::{postgres::PgPool, query, query_as, query_file};
+use tracing::{info, instrument, warn};
+
+/// The unique index ensuring each role owns at most one database, and so belongs to one cluster
+const OWNER_INDEX: &str = "databases_owner_key";
+
+#[derive(Debug)]
+struct Row {
+    name: String,
+    owner: String,
+    origin: String,
+    cluster: Option<String>,
+    retain: bool,
+    created_at: DateTime<Utc>,
+}
+
+impl From<Row> for ManagedDatabase {
+    fn from(row: Row) -> Self {
+        let origin = match row.origin.as_str() {
+            "operator" => Origin::Operator,
+            _ => Origin::Api,
+        };
+
+        ManagedDatabase {
+            name: row.name,
+            owner: row.owner,
+            origin,
+            cluster: row.cluster,
+            retain: row.retain,
+            created_at: row.created_at,
+        }
+    }
+}
+
+/// Ensure the registry schema and table exist
+#[instrument(skip_all)]
+pub(super) async fn ensure_table(pool: &PgPool) -> Result<()> {
+    query!("CREATE SCHEMA IF NOT EXISTS external_postgres")
+        .execute(pool)
+        .await?;
+    query_file!("queries/registry-table.sql")
+        .execute(pool)
+        .await?;
+    query!("ALTER TABLE external_postgres.databases ADD COLUMN IF NOT EXISTS cluster text")
+        .execute(pool)
+        .await?;
+    // Registries from before roles were tracked may already share a role between databases, in
+    // which case the index cannot be created until they are separated
+    let index = query!(
+        "CREATE UNIQUE INDEX IF NOT EXISTS databases_owner_key ON external_postgres.databases (owner)"
+    )
+    .execute(pool)
+    .await;
+    match index {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("23505") => {
+            warn!("some roles own multiple databases, not enforcing that roles are unique")
+        }
+        Err(error) => return Err(error.into()),
+    }
+    query!("CREATE TABLE IF NOT EXISTS external_postgres.keys (name text PRIMARY KEY, value bytea NOT NULL)")
+        .execute(pool)
+        .await?;
+    query!("CREATE TABLE IF NOT EXISTS external_postgres.rotations (name text PRIMARY KEY, rotated_at timestamptz NOT NULL)")
+        .execute(pool)
+        .await?;
+    info!("created database registry if not exists");
+
+    Ok(())
+}
+
+/// Get a secret key shared by every instance, generating it the first time it is requested
+#[instrument(skip(pool))]
+pub(super) async fn key(name: &str, pool: &PgPool) -> Result<Vec<u8>> {
+    let mut generated = [0u8; 32];
+    OsRng.fill_bytes(&mut generated);
+
+    // Only the first instance to get here stores its key, everyone else uses that one
+    query!(
+        "INSERT INTO external_postgres.keys (name, value) VALUES ($1, $2) ON CONFLICT (name) DO NOTHING",
+        name,
+        &generated[..]
+    )
+    .execute(pool)
+    .await?;
+
+    let row = query!(
+        "SELECT value FROM external_postgres.keys WHERE name = $1",
+        name
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.value)
+}
+
+/// Get when a password shared by every instance was last rotated. If it was never recorded, it is
+/// treated as having been rotated now.
+#[instrument(skip(pool))]
+pub(super) async fn rotated_at(name: &str, pool: &PgPool) -> Result<DateTime<Utc>> {
+    query!(
+        "INSERT INTO external_postgres.rotations (name, rotated_at) VALUES ($1, now()) ON CONFLICT (name) DO NOTHING",
+        name
+    )
+    .execute(pool)
+    .await?;
+
+    let row = query!(
+        "SELECT rotated_at FROM external_postgres.rotations WHERE name = $1",
+        name
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.rotated_at)
+}
+
+/// Record that a password shared by every instance was just rotated
+#[instrument(skip(pool))]
+pub(super) async fn record_rotation(name: &str, pool: &PgPool) -> Result<()> {
+    query!(
+        "INSERT INTO external_postgres.rotations (name, rotated_at) VALUES ($1, now()) ON CONFLICT (name) DO UPDATE SET rotated_at = excluded.rotated_at",
+        name
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Get all the registered databases
+#[instrument(skip_all)]
+pub(super) async fn list(pool: &PgPool) -> Result<Vec<ManagedDatabase>> {
+    let rows = query_as!(
+        Row,
+        "SELECT name, owner, origin, cluster, retain, created_at FROM external_postgres.databases ORDER BY name"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+/// Get a registered database by name
+#[instrument(skip(pool))]
+pub(super) async fn get(name: &str, pool: &PgPool) -> Result<Option<ManagedDatabase>> {
+    let row = query_as!(
+        Row,
+        "SELECT name, owner, origin, cluster, retain, created_at FROM external_postgres.databases WHERE name = $1",
+        name
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(Into::into))
+}
+
+/// Add a database to the registry, updating the owner and retain state if it already exists.
+/// Existing databases are only updated if the caller may manage them: the cluster must match the
+/// one that claimed the database, databases created through the API can only be updated without a
+/// cluster, and operator databases registered before clusters were recorded are adopted by the
+/// first cluster to update them. Returns whether the database was registered.
+#[instrument(skip(pool))]
+pub(super) async fn register(
+    name: &str,
+    owner: &str,
+    origin: Origin,
+    cluster: Option<&str>,
+    retain: bool,
+    pool: &PgPool,
+) -> Result<bool> {
+    let result = query!(
+        r#"
+        INSERT INTO external_postgres.databases (name, owner, origin, cluster, retain) VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (name) DO UPDATE SET
+            owner = excluded.owner,
+            cluster = excluded.cluster,
+            retain = excluded.retain
+        WHERE (
+            databases.cluster IS NOT DISTINCT FROM excluded.cluster
+            AND (excluded.cluster IS NOT NULL OR databases.origin = 'api')
+        ) OR (
+            databases.cluster IS NULL
+            AND databases.origin = 'operator'
+            AND excluded.cluster IS NOT NULL
+        )
+        "#,
+        name,
+        owner,
+        origin.as_str(),
+        cluster,
+        retain
+    )
+    .execute(pool)
+    .await;
+
+    let registered = match result {
+        Ok(result) => result.rows_affected() > 0,
+        Err(sqlx::Error::Database(error)) if error.constraint() == Some(OWNER_INDEX) => {
+            return Err(Error::RoleInUse(owner.to_string()))
+        }
+        Err(error) => return Err(error.into()),
+    };
+    if registered {
+        info!("registered database");
+    }
+
+    Ok(registered)
+}
+
+/// Remove a database from the registry
+#[instrument(skip(pool))]
+pub(super) async fn deregister(name: &str, pool: &PgPool) -> Result<()> {
+    query!(
+        "DELETE FROM external_postgres.databases WHERE name = $1",
+        name
+    )
+    .execute(pool)
+    .await?;
+    info!("deregistered database");
+
+    Ok(())
+}
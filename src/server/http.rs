@@ -1,8 +1,8 @@
-use super::{database::Databases, operator::Operator};
+use super::{database::Databases, operator::Clusters};
 use axum::{
     extract::{FromRef, State},
     http::{Request, StatusCode},
-    routing::{delete, get},
+    routing::{delete, get, post},
     Router,
 };
 use sqlx::query;
@@ -17,7 +17,7 @@ mod operator;
 #[derive(Clone)]
 pub struct AppState {
     databases: Databases,
-    operator: Operator,
+    clusters: Clusters,
 }
 
 impl FromRef<AppState> for Databases {
@@ -26,22 +26,37 @@ impl FromRef<AppState> for Databases {
     }
 }
 
-impl FromRef<AppState> for Operator {
+impl FromRef<AppState> for Clusters {
     fn from_ref(input: &AppState) -> Self {
-        input.operator.clone()
+        input.clusters.clone()
     }
 }
 
 /// Build the router for the management interface
-pub fn router(databases: Databases, operator: Operator) -> Router {
+pub fn router(databases: Databases, clusters: Clusters) -> Router {
     Router::new()
         .route("/health", get(health))
         .route("/databases", get(database::list).post(database::ensure))
+        .route("/databases/lookup-function", get(database::lookup_function))
         .route("/databases/:database", delete(database::delete))
+        .route("/databases/:database/rotate", post(database::rotate))
+        .route("/operators", get(operator::list))
         .route(
             "/operator/state",
             get(operator::get_state).post(operator::change_state),
         )
+        .route(
+            "/operator/config",
+            get(operator::get_config).put(operator::change_config),
+        )
+        .route(
+            "/operators/:cluster/state",
+            get(operator::get_state).post(operator::change_state),
+        )
+        .route(
+            "/operators/:cluster/config",
+            get(operator::get_config).put(operator::change_config),
+        )
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(MakeSpanWithId)
@@ -50,7 +65,7 @@ pub fn router(databases: Databases, operator: Operator) -> Router {
         )
         .with_state(AppState {
             databases,
-            operator,
+            clusters,
         })
 }
 
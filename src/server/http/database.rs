@@ -1,7 +1,9 @@
-use super::error::Result;
+use super::error::{Error, Result};
 use crate::{
-    models::database::{CreateRequest, DeleteOptions},
-    server::database::Databases,
+    models::database::{
+        CreateRequest, DeleteOptions, LookupFunctionVersion, ManagedDatabase, Origin,
+    },
+    server::{database::Databases, operator::Clusters},
 };
 use axum::{
     extract::{Path, Query, State},
@@ -11,8 +13,8 @@ use axum::{
 use tracing::instrument;
 
 #[instrument(name = "database_list", skip_all)]
-pub async fn list(State(databases): State<Databases>) -> Json<Vec<String>> {
-    Json(databases.managed_databases())
+pub async fn list(State(databases): State<Databases>) -> Result<Json<Vec<ManagedDatabase>>> {
+    Ok(Json(databases.managed_databases().await?))
 }
 
 #[instrument(name = "database_ensure", skip(databases))]
@@ -20,7 +22,16 @@ pub async fn ensure(
     State(databases): State<Databases>,
     Json(request): Json<CreateRequest>,
 ) -> Result<StatusCode> {
-    databases.ensure(&request.name, &request.password).await?;
+    databases
+        .ensure(
+            &request.name,
+            request.username.as_ref().unwrap_or(&request.name),
+            &request.password,
+            Origin::Api,
+            request.cluster.as_deref(),
+            request.retain,
+        )
+        .await?;
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -31,8 +42,33 @@ pub async fn delete(
     State(databases): State<Databases>,
 ) -> Result<StatusCode> {
     databases
-        .remove(&name, options.retain.unwrap_or_default())
+        .remove(&name, options.retain, options.cluster.as_deref())
         .await?;
 
     Ok(StatusCode::NO_CONTENT)
 }
+
+#[instrument(name = "database_rotate", skip(databases, clusters))]
+pub async fn rotate(
+    Path(name): Path<String>,
+    State(databases): State<Databases>,
+    State(clusters): State<Clusters>,
+) -> Result<StatusCode> {
+    let database = databases.managed_database(&name).await?;
+    if database.origin != Origin::Operator {
+        return Err(Error::NotOperatorManaged);
+    }
+
+    clusters
+        .request_rotation(&name, database.cluster.as_deref())
+        .await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[instrument(name = "database_lookup_function", skip_all)]
+pub async fn lookup_function(
+    State(databases): State<Databases>,
+) -> Result<Json<Vec<LookupFunctionVersion>>> {
+    Ok(Json(databases.lookup_function_versions().await?))
+}
@@ -1,4 +1,7 @@
-use crate::{models::ErrorResponse, server::database};
+use crate::{
+    models::ErrorResponse,
+    server::{database, operator},
+};
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
@@ -13,6 +16,10 @@ pub enum Error {
     #[error(transparent)]
     Database(#[from] database::Error),
     #[error(transparent)]
+    Operator(#[from] operator::Error),
+    #[error("only databases managed by the operator can be rotated")]
+    NotOperatorManaged,
+    #[error(transparent)]
     Sqlx(#[from] sqlx::Error),
 }
 
@@ -20,7 +27,28 @@ impl IntoResponse for Error {
     fn into_response(self) -> Response {
         let message = format!("{self}");
         let code = match self {
-            Self::Database(_) | Self::Sqlx(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Database(database::Error::InvalidName(_)) => StatusCode::BAD_REQUEST,
+            Self::Database(database::Error::NotManaged) => StatusCode::NOT_FOUND,
+            Self::Database(
+                database::Error::ClaimedByCluster(_)
+                | database::Error::ClusterRequired
+                | database::Error::ManagedByApi
+                | database::Error::RoleInUse(_)
+                | database::Error::Unmanaged(..),
+            ) => StatusCode::CONFLICT,
+            Self::Operator(operator::Error::NotRunning) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Operator(operator::Error::NotFound | operator::Error::UnknownCluster(_)) => {
+                StatusCode::NOT_FOUND
+            }
+            Self::Operator(operator::Error::UnknownContext(_) | operator::Error::Kubeconfig(_)) => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::Operator(operator::Error::NotGenerated) | Self::NotOperatorManaged => {
+                StatusCode::CONFLICT
+            }
+            Self::Database(_) | Self::Operator(_) | Self::Sqlx(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
         };
 
         let mut response = Json(ErrorResponse {
@@ -1,26 +1,72 @@
+use super::error::Result;
 use crate::{
-    models::operator::{ChangeStateRequest, ChangeStateResponse, StateResponse, Status},
-    server::operator::Operator,
+    models::operator::{
+        ChangeStateRequest, ChangeStateResponse, Configuration, StateResponse, Status,
+    },
+    server::operator::{Clusters, Operator},
+};
+use axum::{
+    extract::{Path, State},
+    Json,
 };
-use axum::{extract::State, Json};
 use tracing::instrument;
 
-#[instrument(name = "operator_get_state", skip_all)]
-pub async fn get_state(State(operator): State<Operator>) -> Json<StateResponse> {
-    Json(StateResponse {
-        running: operator.status(),
-    })
+#[instrument(name = "operator_list", skip_all)]
+pub async fn list(State(clusters): State<Clusters>) -> Json<Vec<StateResponse>> {
+    Json(clusters.states())
+}
+
+#[instrument(name = "operator_get_state", skip(clusters))]
+pub async fn get_state(
+    cluster: Option<Path<String>>,
+    State(clusters): State<Clusters>,
+) -> Result<Json<StateResponse>> {
+    Ok(Json(operator(&clusters, cluster)?.state()))
 }
 
-#[instrument(name = "operator_change_state", skip_all, fields(desired = ?request.desired))]
+#[instrument(name = "operator_change_state", skip(clusters, request), fields(desired = ?request.desired))]
 pub async fn change_state(
-    State(operator): State<Operator>,
+    cluster: Option<Path<String>>,
+    State(clusters): State<Clusters>,
     Json(request): Json<ChangeStateRequest>,
-) -> Json<ChangeStateResponse> {
+) -> Result<Json<ChangeStateResponse>> {
+    let operator = operator(&clusters, cluster)?;
     let success = match request.desired {
         Status::Enabled => operator.start(),
         Status::Disabled => operator.stop().await,
     };
 
-    Json(ChangeStateResponse { success })
+    Ok(Json(ChangeStateResponse { success }))
+}
+
+#[instrument(name = "operator_get_config", skip(clusters))]
+pub async fn get_config(
+    cluster: Option<Path<String>>,
+    State(clusters): State<Clusters>,
+) -> Result<Json<Configuration>> {
+    Ok(Json(operator(&clusters, cluster)?.configuration()))
+}
+
+#[instrument(name = "operator_change_config", skip(clusters))]
+pub async fn change_config(
+    cluster: Option<Path<String>>,
+    State(clusters): State<Clusters>,
+    Json(request): Json<Configuration>,
+) -> Result<Json<Configuration>> {
+    let configuration = operator(&clusters, cluster)?
+        .configure(request.kubeconfig, request.context)
+        .await?;
+
+    Ok(Json(configuration))
+}
+
+/// Find the operator for the cluster in the path, falling back to the default cluster for the
+/// unnamed `/operator/...` routes
+fn operator(clusters: &Clusters, cluster: Option<Path<String>>) -> Result<&Operator> {
+    let operator = match cluster {
+        Some(Path(cluster)) => clusters.get(&cluster)?,
+        None => clusters.default()?,
+    };
+
+    Ok(operator)
 }
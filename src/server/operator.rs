@@ -1,26 +1,73 @@
 use super::database::{self, Databases};
-use clap::Args;
+use crate::models::{
+    database::Origin,
+    operator::{Configuration, ControllerState, StateResponse},
+};
+use chrono::Utc;
+use clap::{Args, ValueEnum};
 use futures::StreamExt;
-use k8s_openapi::{api::core::v1::Secret, apimachinery::pkg::apis::meta::v1::ObjectMeta};
+use k8s_openapi::{
+    api::core::v1::{Namespace, Secret},
+    apimachinery::pkg::apis::meta::v1::{LabelSelector, ObjectMeta},
+    ByteString,
+};
 use kube::{
-    api::{DeleteParams, ListParams, Patch, PatchParams},
+    api::{ListParams, Patch, PatchParams},
     client::Client,
-    config::{Config, KubeConfigOptions, Kubeconfig},
+    config::{Config, InClusterError, KubeConfigOptions, Kubeconfig, KubeconfigError},
     runtime::{
         controller::Action,
-        finalizer::{finalizer, Event},
+        finalizer::{self, finalizer, Event},
+        reflector::ObjectRef,
         wait::{self, await_condition, conditions},
         Controller,
     },
     Api, CustomResource, CustomResourceExt, ResourceExt,
 };
 use parking_lot::Mutex;
-use schemars::JsonSchema;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use sqlx::postgres::PgSslMode;
-use std::{collections::BTreeMap, path::PathBuf, sync::Arc, time::Duration};
-use tokio::{sync::oneshot, task::JoinHandle};
-use tracing::{debug, error, info, instrument, warn};
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{oneshot, watch, Mutex as AsyncMutex},
+    task::JoinHandle,
+};
+use tracing::{debug, error, info, instrument, warn, Instrument};
+
+mod auth_secret;
+mod backoff;
+mod clusters;
+mod connection_url;
+mod drift;
+mod events;
+mod leader;
+mod namespaces;
+mod password;
+mod rotation;
+mod secrets;
+mod state;
+mod status;
+mod template;
+mod watcher;
+
+pub use auth_secret::Options as AuthSecretOptions;
+use backoff::Backoff;
+pub use clusters::{Cluster, Clusters, Options as ClusterOptions};
+use events::{Events, Reason};
+use leader::{Elector, Leadership};
+use status::{ConditionType, DatabaseStatus};
+use template::Variables;
 
 #[derive(Clone, Debug, Args)]
 pub struct ConnectionInfo {
@@ -49,18 +96,63 @@ pub struct ConnectionInfo {
     pub sslmode: PgSslMode,
 }
 
-impl ConnectionInfo {
-    fn into_secret_data(self) -> BTreeMap<String, String> {
-        let mut data = BTreeMap::new();
-
-        data.insert(String::from("PGHOST"), self.remote_host);
-        data.insert(String::from("PGPORT"), format!("{}", self.remote_port));
-        data.insert(
-            String::from("PGSSLMODE"),
-            format!("{:?}", self.sslmode).to_lowercase(),
-        );
+#[derive(Clone, Debug, Args)]
+pub struct ControllerOptions {
+    /// How often to check databases for drift and reconcile them, even if they have not changed
+    #[arg(
+        long = "kube-resync-interval",
+        default_value = "10m",
+        env = "KUBE_RESYNC_INTERVAL",
+        value_parser = humantime::parse_duration
+    )]
+    pub resync_interval: Duration,
+
+    /// The longest to wait before retrying a database that failed to reconcile
+    #[arg(
+        long = "kube-backoff-max",
+        default_value = "5m",
+        env = "KUBE_BACKOFF_MAX",
+        value_parser = humantime::parse_duration
+    )]
+    pub backoff_max: Duration,
+
+    /// How often to check the kubeconfig for changes, starting or restarting the controller when
+    /// it is created or modified
+    #[arg(
+        long = "kubeconfig-watch-interval",
+        default_value = "10s",
+        env = "KUBECONFIG_WATCH_INTERVAL",
+        value_parser = humantime::parse_duration
+    )]
+    pub watch_interval: Duration,
+
+    #[command(flatten)]
+    pub leader_election: leader::Options,
+}
 
-        data
+/// Where to load the configuration for connecting to Kubernetes from
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum ConfigSource {
+    /// Use the kubeconfig if it exists, otherwise the in-cluster configuration
+    #[default]
+    Auto,
+    /// Only use the kubeconfig
+    Kubeconfig,
+    /// Only use the in-cluster service account
+    InCluster,
+}
+
+impl ConnectionInfo {
+    /// The SSL mode as understood by libpq
+    fn sslmode(&self) -> &'static str {
+        match self.sslmode {
+            PgSslMode::Disable => "disable",
+            PgSslMode::Allow => "allow",
+            PgSslMode::Prefer => "prefer",
+            PgSslMode::Require => "require",
+            PgSslMode::VerifyCa => "verify-ca",
+            PgSslMode::VerifyFull => "verify-full",
+        }
     }
 }
 
@@ -69,55 +161,106 @@ pub struct Operator(Arc<KubeInner>);
 
 #[derive(Debug)]
 struct KubeInner {
+    /// The name of the cluster, recorded as the owner of the databases it provisions
+    cluster: String,
     databases: Databases,
-    kubeconfig: PathBuf,
-    kube_context: Option<String>,
+    location: Mutex<Location>,
+    config_source: ConfigSource,
     handle: Mutex<Option<KubeControllerHandle>>,
-    secret_data: BTreeMap<String, String>,
+    /// Held while the controller is being restarted so concurrent restarts do not interleave
+    restarting: AsyncMutex<()>,
+    /// Whether the controller should be running, cleared when it is explicitly stopped
+    enabled: AtomicBool,
+    connection_info: ConnectionInfo,
+    auth_secret: AuthSecretOptions,
+    /// Whether the auth user's password is generated and rotated while leading this cluster
+    manage_auth_password: bool,
+    controller: ControllerOptions,
+    identity: String,
+    leadership: Mutex<Option<Leadership>>,
+    state: Mutex<state::State>,
+}
+
+/// Where to load the kubeconfig from and which context to use
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Location {
+    path: PathBuf,
+    context: Option<String>,
 }
 
 #[derive(Debug)]
 struct KubeControllerHandle {
-    stop: oneshot::Sender<()>,
+    stop: watch::Sender<bool>,
     join: JoinHandle<()>,
 }
 
 impl Operator {
-    /// Create a new kubernetes operator
-    #[instrument(name = "operator", skip(connection_info, databases))]
+    /// Create a new kubernetes operator for the cluster
+    #[instrument(
+        name = "operator",
+        skip_all,
+        fields(cluster = %cluster.name, kubeconfig = %cluster.kubeconfig.display(), context = ?cluster.context)
+    )]
     pub fn new(
-        kubeconfig: PathBuf,
-        kube_context: Option<String>,
-        connection_info: ConnectionInfo,
+        cluster: Cluster,
+        config_source: ConfigSource,
+        auth_secret: AuthSecretOptions,
+        manage_auth_password: bool,
+        controller: ControllerOptions,
         databases: Databases,
     ) -> Self {
-        let kubeconfig = shellexpand::tilde(&kubeconfig.as_os_str().to_string_lossy())
-            .to_string()
-            .into();
+        let location = Location {
+            path: expand_tilde(&cluster.kubeconfig),
+            context: cluster.context,
+        };
 
         let operator = Operator(Arc::new(KubeInner {
+            cluster: cluster.name,
             databases,
-            kubeconfig,
-            kube_context,
+            location: Mutex::new(location),
+            config_source,
             handle: Mutex::default(),
-            secret_data: connection_info.into_secret_data(),
+            restarting: AsyncMutex::default(),
+            enabled: AtomicBool::new(true),
+            connection_info: cluster.connection_info,
+            auth_secret,
+            manage_auth_password,
+            identity: controller.leader_election.identity(),
+            controller,
+            leadership: Mutex::default(),
+            state: Mutex::default(),
         }));
 
-        // Launch the controller if the kubeconfig exists
-        if operator.0.kubeconfig.exists() {
-            info!("kubeconfig exists, launching...");
+        // Launch the controller if the configuration exists
+        if operator.config_available() {
+            info!("kubernetes configuration exists, launching...");
             operator.spawn();
         } else {
-            warn!(path = %operator.0.kubeconfig.display(), "could not find kubeconfig");
-            info!("run `external-postgres operator enable` once the kubeconfig exists");
+            warn!(
+                path = %operator.kubeconfig_path().display(),
+                source = ?operator.0.config_source,
+                "could not find kubernetes configuration"
+            );
+            info!("waiting for the kubeconfig to be created");
         }
 
+        tokio::spawn(watcher::watch(
+            operator.clone(),
+            operator.0.controller.watch_interval,
+        ));
+
         operator
     }
 
     /// Try to spawn the operator
-    #[instrument(skip_all, fields(path = %self.0.kubeconfig.display()))]
+    #[instrument(skip_all, fields(cluster = %self.0.cluster, path = %self.kubeconfig_path().display()))]
     pub fn start(&self) -> bool {
+        self.0.enabled.store(true, Ordering::Relaxed);
+        self.launch()
+    }
+
+    /// Spawn the controller if it is not already running and the configuration exists
+    fn launch(&self) -> bool {
         {
             let handle = self.0.handle.lock();
             if handle.is_some() {
@@ -125,25 +268,121 @@ impl Operator {
             }
         }
 
-        let exists = self.0.kubeconfig.exists();
-        if exists {
-            info!("kubeconfig exists, launching controller");
+        let available = self.config_available();
+        if available {
+            info!("kubernetes configuration exists, launching controller");
             self.spawn();
         }
 
-        exists
+        available
+    }
+
+    /// Whether the controller should be running, as it was not explicitly stopped
+    fn enabled(&self) -> bool {
+        self.0.enabled.load(Ordering::Relaxed)
+    }
+
+    /// The path to the kubeconfig currently in use
+    fn kubeconfig_path(&self) -> PathBuf {
+        self.0.location.lock().path.clone()
+    }
+
+    /// Check whether the configuration for connecting to Kubernetes can be loaded
+    fn config_available(&self) -> bool {
+        match self.0.config_source {
+            ConfigSource::Auto => self.kubeconfig_path().exists() || in_cluster(),
+            ConfigSource::Kubeconfig => self.kubeconfig_path().exists(),
+            ConfigSource::InCluster => in_cluster(),
+        }
+    }
+
+    /// Get the kubeconfig and context used to connect to Kubernetes
+    pub fn configuration(&self) -> Configuration {
+        let location = self.0.location.lock();
+
+        Configuration {
+            kubeconfig: location.path.clone(),
+            context: location.context.clone(),
+        }
+    }
+
+    /// Change the kubeconfig and context used to connect to Kubernetes, restarting the controller
+    /// if it is enabled
+    #[instrument(skip(self), fields(cluster = %self.0.cluster))]
+    pub async fn configure(
+        &self,
+        kubeconfig: PathBuf,
+        context: Option<String>,
+    ) -> Result<Configuration> {
+        let location = Location {
+            path: expand_tilde(&kubeconfig),
+            context,
+        };
+
+        // The kubeconfig may not exist yet, in which case it is checked once it gets created
+        if location.path.exists() {
+            let kubeconfig = Kubeconfig::read_from(&location.path)?;
+            if let Some(context) = &location.context {
+                if !kubeconfig
+                    .contexts
+                    .iter()
+                    .any(|named| &named.name == context)
+                {
+                    return Err(Error::UnknownContext(context.clone()));
+                }
+            }
+        }
+
+        let _guard = self.0.restarting.lock().await;
+        let changed = {
+            let mut current = self.0.location.lock();
+            let changed = *current != location;
+            *current = location;
+            changed
+        };
+
+        if changed {
+            info!("kubernetes configuration changed");
+            self.relaunch().await;
+        }
+
+        Ok(self.configuration())
+    }
+
+    /// Restart the controller so it picks up changes to its configuration, unless it was
+    /// explicitly stopped
+    async fn restart(&self) {
+        let _guard = self.0.restarting.lock().await;
+        self.relaunch().await;
+    }
+
+    /// Stop the controller and launch it again if it is enabled
+    async fn relaunch(&self) {
+        self.halt().await;
+
+        if self.enabled() {
+            info!("restarting controller");
+            self.launch();
+        }
     }
 
     /// Stop the operator
-    #[instrument(skip_all)]
+    #[instrument(skip_all, fields(cluster = %self.0.cluster))]
     pub async fn stop(&self) -> bool {
+        self.0.enabled.store(false, Ordering::Relaxed);
+        self.halt().await
+    }
+
+    /// Stop the controller without changing whether it is enabled
+    async fn halt(&self) -> bool {
         let handle = {
             let mut handle = self.0.handle.lock();
             handle.take()
         };
 
         if let Some(handle) = handle {
-            handle.stop.send(()).unwrap();
+            // The controller may have already exited, in which case there is nothing to stop
+            let _ = handle.stop.send(true);
 
             if let Err(error) = handle.join.await {
                 // Simply log the error, as there's nothing we can do about it
@@ -156,84 +395,352 @@ impl Operator {
         }
     }
 
-    /// Check whether the operator is running
+    /// Check whether the operator is running, which is never the case for followers when leader
+    /// election is enabled
     pub fn status(&self) -> bool {
-        let handle = self.0.handle.lock();
-        handle.is_some()
+        self.0.state.lock().state == ControllerState::Running
+    }
+
+    /// Get the current state of the controller
+    pub fn state(&self) -> StateResponse {
+        let state = self.0.state.lock().clone();
+
+        StateResponse {
+            cluster: self.0.cluster.clone(),
+            running: state.state == ControllerState::Running,
+            state: state.state,
+            since: state.since,
+            last_error: state.last_error,
+            last_error_time: state.last_error_time,
+            leader: self.leader(),
+        }
+    }
+
+    /// Move the controller to a new state
+    fn transition(&self, to: ControllerState) {
+        let mut state = self.0.state.lock();
+        state.transition(to);
+    }
+
+    /// The identity of the instance holding the lease, if leader election is enabled
+    pub fn leader(&self) -> Option<String> {
+        match &*self.0.leadership.lock() {
+            Some(Leadership::Leader) => Some(self.0.identity.clone()),
+            Some(Leadership::Follower(leader)) => leader.clone(),
+            None => None,
+        }
+    }
+
+    /// Record who holds the lease
+    fn set_leadership(&self, leadership: Option<Leadership>) {
+        match &leadership {
+            Some(Leadership::Leader) => self.transition(ControllerState::Running),
+            Some(Leadership::Follower(_)) => self.transition(ControllerState::Following),
+            None => {}
+        }
+
+        let mut current = self.0.leadership.lock();
+        *current = leadership;
     }
 
     /// Launch the operator in a separate task
     fn spawn(&self) {
-        let (tx, rx) = oneshot::channel();
+        let (tx, rx) = watch::channel(false);
         let mut handle = self.0.handle.lock();
         *handle = Some(KubeControllerHandle {
             stop: tx,
-            join: tokio::spawn(self.clone().operator(rx)),
+            join: tokio::spawn(self.clone().supervise(rx)),
         });
     }
 
-    /// Runs the kubernetes operator
-    async fn operator(self, stop: oneshot::Receiver<()>) {
-        let kubeconfig = Kubeconfig::read_from(&self.0.kubeconfig).unwrap();
+    /// Request the generated password for a database be rotated on the next reconcile
+    #[instrument(skip(self), fields(cluster = %self.0.cluster))]
+    pub async fn request_rotation(&self, database: &str) -> Result<()> {
+        if !self.status() {
+            return Err(Error::NotRunning);
+        }
+
+        let api = Api::<Database>::all(self.client().await?);
+        let object = api
+            .list(&ListParams::default())
+            .await?
+            .into_iter()
+            .find(|object| name_for_database(object).ok().as_deref() == Some(database))
+            .ok_or(Error::NotFound)?;
+        if !matches!(object.spec.password, DatabasePassword::Generate(_)) {
+            return Err(Error::NotGenerated);
+        }
+
+        api.patch(
+            &object.name_any(),
+            &PatchParams::default(),
+            &Patch::Merge(json!({
+                "metadata": {
+                    "annotations": {
+                        rotation::REQUESTED_AT_ANNOTATION: Utc::now().to_rfc3339(),
+                    },
+                },
+            })),
+        )
+        .await?;
+        info!("requested password rotation");
+
+        Ok(())
+    }
+
+    /// Create a new client from the kubeconfig or in-cluster configuration
+    async fn client(&self) -> Result<Client> {
+        let location = self.0.location.lock().clone();
+        let use_kubeconfig = match self.0.config_source {
+            ConfigSource::Auto => location.path.exists(),
+            ConfigSource::Kubeconfig => true,
+            ConfigSource::InCluster => false,
+        };
+        if !use_kubeconfig {
+            debug!("using in-cluster configuration");
+            return Ok(Client::try_from(Config::incluster()?)?);
+        }
+
+        let kubeconfig = Kubeconfig::read_from(&location.path)?;
         let config = Config::from_custom_kubeconfig(
             kubeconfig,
             &KubeConfigOptions {
-                context: self.0.kube_context.clone(),
+                context: location.context,
                 ..Default::default()
             },
         )
-        .await
-        .unwrap();
+        .await?;
+
+        Ok(Client::try_from(config)?)
+    }
+
+    /// Runs the kubernetes operator, restarting it with backoff whenever it fails
+    #[instrument(skip_all, fields(cluster = %self.0.cluster))]
+    async fn supervise(self, stop: watch::Receiver<bool>) {
+        let mut delay = INITIAL_RESTART_DELAY;
+
+        loop {
+            self.transition(ControllerState::Starting);
+            let started = Instant::now();
+
+            // Run in a separate task so panics can be recovered from
+            let run = self.clone().operator(stop.clone()).in_current_span();
+            let error = match tokio::spawn(run).await {
+                Ok(Ok(())) => break,
+                Ok(Err(error)) => error.to_string(),
+                Err(error) => format!("controller task failed: {error}"),
+            };
+            self.set_leadership(None);
 
-        let client = Client::try_from(config).unwrap();
+            // Only keep backing off if the operator keeps failing quickly
+            if started.elapsed() > MAX_RESTART_DELAY {
+                delay = INITIAL_RESTART_DELAY;
+            }
+            error!(%error, ?delay, "operator failed, restarting");
+            {
+                let mut state = self.0.state.lock();
+                state.failed(error);
+            }
+
+            tokio::select! {
+                _ = stopped(stop.clone()) => break,
+                _ = tokio::time::sleep(delay) => {},
+            }
+            delay = (delay * 2).min(MAX_RESTART_DELAY);
+        }
+
+        self.transition(ControllerState::Stopped);
+    }
+
+    /// Runs the kubernetes operator until it is stopped
+    async fn operator(self, stop: watch::Receiver<bool>) -> Result<()> {
+        let client = self.client().await?;
         if let Err(error) = apply_crd(client.clone()).await {
             error!(%error, "failed to apply CRD");
         }
 
+        let options = &self.0.controller.leader_election;
+        if !options.enabled {
+            self.transition(ControllerState::Running);
+            self.controller(client, stopped(stop)).await;
+            return Ok(());
+        }
+
+        // Only run the controller while holding the lease, going back to waiting for it when lost
+        let elector = Elector::new(client.clone(), options, self.0.identity.clone());
+        loop {
+            tokio::select! {
+                _ = stopped(stop.clone()) => break,
+                _ = elector.acquire(|leadership| self.set_leadership(Some(leadership))) => {}
+            }
+
+            let (lost_tx, lost_rx) = oneshot::channel();
+            let hold = tokio::spawn(elector.clone().hold(lost_tx));
+            let shutdown = {
+                let operator = self.clone();
+                let stop = stop.clone();
+
+                async move {
+                    tokio::select! {
+                        _ = stopped(stop) => {}
+                        Ok(leadership) = lost_rx => operator.set_leadership(Some(leadership)),
+                    }
+                }
+            };
+            self.controller(client.clone(), shutdown).await;
+            hold.abort();
+
+            if *stop.borrow() {
+                elector.release().await;
+                break;
+            }
+            info!("lost leadership, stopped controller");
+        }
+
+        self.set_leadership(None);
+        Ok(())
+    }
+
+    /// Runs the controller until the shutdown future completes
+    async fn controller(
+        &self,
+        client: Client,
+        shutdown: impl Future<Output = ()> + Send + Sync + 'static,
+    ) {
+        let events = Events::new(client.clone());
+        let backoff = Backoff::new(self.0.controller.backoff_max);
+
+        let auth_secret = tokio::spawn(auth_secret::sync(
+            client.clone(),
+            self.0.auth_secret.clone(),
+            self.0.databases.auth_credentials(),
+        ));
+        let auth_password = self.0.manage_auth_password.then(|| {
+            tokio::spawn(auth_secret::manage(
+                client.clone(),
+                self.0.auth_secret.clone(),
+                self.0.databases.clone(),
+            ))
+        });
+
         let databases = Api::<Database>::all(client.clone());
-        Controller::new(databases, ListParams::default())
+        let controller = Controller::new(databases, ListParams::default());
+
+        // Reconcile databases whenever the secret their password comes from changes
+        let store = controller.store();
+        let secrets = Api::<Secret>::all(client.clone());
+        let default_namespace = client.default_namespace().to_string();
+
+        // Reconcile databases whenever a namespace starts or stops matching their selector
+        let namespace_store = controller.store();
+        let namespaces = Api::<Namespace>::all(client.clone());
+
+        controller
+            .watches(secrets, ListParams::default(), move |secret| {
+                store
+                    .state()
+                    .into_iter()
+                    .filter(|object| password_references_secret(object, &secret, &default_namespace))
+                    .map(|object| ObjectRef::from_obj(&*object))
+                    .collect::<Vec<_>>()
+            })
+            .watches(namespaces, ListParams::default(), move |namespace| {
+                namespaces::affected(namespace_store.state(), &namespace)
+            })
             .graceful_shutdown_on(async {
-                stop.await.unwrap();
+                shutdown.await;
                 debug!("shutdown signal received");
             })
             .run(
                 |database, _| {
                     let databases_api = Api::<Database>::all(client.clone());
                     let client = client.clone();
+                    let events = events.clone();
+                    let operator = self.clone();
 
-                    let base_secret_data = self.0.secret_data.clone();
-                    let databases = self.0.databases.clone();
+                    let backoff = backoff.clone();
+                    let reference = ObjectRef::from_obj(&*database);
 
                     async move {
-                        finalizer(
+                        let result = finalizer(
                             &databases_api,
                             "external-postgres.wafflehacks.cloud/cleanup",
                             database,
                             |event| async {
                                 match event {
                                     Event::Apply(object) => {
-                                        apply(object, databases, base_secret_data, client).await
+                                        apply(object, operator, client, events).await
                                     }
                                     Event::Cleanup(object) => {
-                                        cleanup(object, databases, client).await
+                                        cleanup(object, operator, client, events).await
                                     }
                                 }
                             },
                         )
-                        .await
+                        .await;
+
+                        if result.is_ok() {
+                            backoff.succeeded(&reference);
+                        }
+                        result
                     }
                 },
                 |object, error, _| {
                     use std::error::Error;
 
                     let source = error.source().map(ToString::to_string).unwrap_or_default();
-                    error!(r#for = object.name_any(), %error, %source, "failed to reconcile");
-                    Action::requeue(Duration::from_secs(5))
+                    let transient = is_transient(error);
+                    error!(r#for = object.name_any(), %error, %source, %transient, "failed to reconcile");
+
+                    // Failures while applying are already reported in the status
+                    if !matches!(error, finalizer::Error::ApplyFailed(_)) {
+                        tokio::spawn(report_failure(
+                            object.clone(),
+                            error.to_string(),
+                            transient,
+                            client.clone(),
+                        ));
+                    }
+
+                    // Permanent failures need the spec or environment to change, which will trigger
+                    // a reconcile on its own, so only check back at the regular resync interval
+                    if transient {
+                        Action::requeue(backoff.failed(ObjectRef::from_obj(&*object)))
+                    } else {
+                        backoff.succeeded(&ObjectRef::from_obj(&*object));
+                        Action::requeue(self.0.controller.resync_interval)
+                    }
                 },
                 Arc::new(()),
             )
             .for_each(|_| futures::future::ready(()))
             .await;
+
+        auth_secret.abort();
+        if let Some(auth_password) = auth_password {
+            auth_password.abort();
+        }
+    }
+}
+
+/// Check whether the process is running in a pod with a service account
+fn in_cluster() -> bool {
+    std::env::var_os("KUBERNETES_SERVICE_HOST").is_some()
+        && Path::new("/var/run/secrets/kubernetes.io/serviceaccount/token").exists()
+}
+
+/// How long to wait before restarting the operator after it first fails
+const INITIAL_RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// The longest to wait before restarting the operator after repeated failures
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Wait until the operator is requested to stop
+async fn stopped(mut stop: watch::Receiver<bool>) {
+    while !*stop.borrow_and_update() {
+        if stop.changed().await.is_err() {
+            return;
+        }
     }
 }
 
@@ -241,63 +748,263 @@ impl Operator {
 #[instrument(skip_all)]
 async fn apply(
     object: Arc<Database>,
-    databases: Databases,
-    mut secret_data: BTreeMap<String, String>,
+    operator: Operator,
     client: Client,
+    events: Events,
 ) -> Result<Action> {
-    let name = name_for_database(&object)?;
-    let password = password_from_spec(&object, client.clone()).await?;
+    let mut status = DatabaseStatus::from_object(&object);
 
-    databases.ensure(&name, &password).await?;
-    info!("ensured database exists");
+    let result = reconcile(&object, &operator, client.clone(), &mut status, &events).await;
+    if let Err(error) = &result {
+        status.failed(error, error.is_transient());
+
+        let reason = match error {
+            Error::NoPassword => Reason::SecretMissing,
+            _ => Reason::ReconcileFailed,
+        };
+        events.publish(&object, reason, error.to_string()).await;
+    }
+    status.patch(&object, client).await?;
+
+    result
+}
+
+/// Ensure the database exists and the connection secrets are up-to-date, recording the progress in
+/// the status
+async fn reconcile(
+    object: &Database,
+    operator: &Operator,
+    client: Client,
+    status: &mut DatabaseStatus,
+    events: &Events,
+) -> Result<Action> {
+    let databases = &operator.0.databases;
+    let resync_interval = operator.0.controller.resync_interval;
+
+    let name = name_for_database(object)?;
+    let username = username_for_database(object)?;
+
+    // The names cannot change once the database is provisioned
+    if status
+        .database_name()
+        .is_some_and(|recorded| recorded != name)
+    {
+        return Err(Error::ImmutableName("databaseName"));
+    }
+    if status
+        .username()
+        .is_some_and(|recorded| recorded != username)
+    {
+        return Err(Error::ImmutableName("username"));
+    }
+
+    let password = match password_from_spec(object, client.clone(), status).await {
+        Ok(password) => {
+            status.set(ConditionType::PasswordResolved, true, "Resolved", "");
+            password
+        }
+        Err(error) => {
+            status.set(
+                ConditionType::PasswordResolved,
+                false,
+                "Unresolved",
+                error.to_string(),
+            );
+            return Err(error);
+        }
+    };
 
     // Populate the secret data
-    secret_data.insert(String::from("PGUSER"), name.clone());
-    secret_data.insert(String::from("PGPASSWORD"), password.clone());
-    secret_data.insert(String::from("PGDATABASE"), name.clone());
-
-    secret_data.insert(
-        String::from("DATABASE_URL"),
-        format!(
-            "postgresql://{}:{}@{}:{}/{}?sslmode={}",
-            name,
-            password,
-            secret_data.get("PGHOST").unwrap(),
-            secret_data.get("PGPORT").unwrap(),
-            name,
-            secret_data.get("PGSSLMODE").unwrap()
-        ),
+    let connection_info = &operator.0.connection_info;
+    let secret_data = object.spec.secret.render(&Variables {
+        host: &connection_info.remote_host,
+        port: connection_info.remote_port,
+        user: &username,
+        password: &password,
+        database: &name,
+        sslmode: connection_info.sslmode(),
+        parameters: &object.spec.secret.url_parameters,
+    })?;
+
+    let secret_name = secret_name_for_database(object);
+    let namespaces = namespaces::desired(&object.spec.secret, client.clone()).await?;
+
+    // Report any drift before it gets repaired
+    let drift = drift::detect(
+        databases,
+        &namespaces,
+        &secret_name,
+        &secret_data,
+        client.clone(),
+        status,
+    )
+    .await?;
+    if !drift.is_empty() {
+        status.drift_checked(drift.clone());
+        status.patch(object, client.clone()).await?;
+
+        let note = format!("repairing drift: {}", drift.join("; "));
+        events.publish(object, Reason::DriftDetected, note).await;
+    } else {
+        status.drift_checked(drift);
+    }
+
+    let created = databases
+        .ensure(
+            &name,
+            &username,
+            &password,
+            Origin::Operator,
+            Some(&operator.0.cluster),
+            object.spec.retain_on_delete,
+        )
+        .await?;
+    let digest_key = databases.digest_key();
+    let password_changed = status.provisioned(
+        &name,
+        &username,
+        drift::hash_password(&digest_key, &password),
     );
+    info!("ensured database exists");
 
-    let secret_name = secret_name_for_database(&object);
-    for namespace in &object.spec.secret.namespaces {
+    if created {
+        let note = format!("created database {name} owned by {username}");
+        events.publish(object, Reason::Created, note).await;
+    } else if password_changed {
+        let note = format!("set password for user {username}");
+        events.publish(object, Reason::PasswordUpdated, note).await;
+    }
+
+    // Written as data rather than stringData so that server-side apply removes keys that are no
+    // longer part of the layout
+    let data = secret_data
+        .iter()
+        .map(|(key, value)| (key.clone(), ByteString(value.clone().into_bytes())))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut secret_namespaces = Vec::new();
+    let mut replicated = Vec::new();
+    for namespace in &namespaces {
         let secrets = Api::<Secret>::namespaced(client.clone(), namespace);
-        secrets
-            .patch(
-                &secret_name,
-                &PatchParams::apply("external-postgres.wafflehacks.cloud").force(),
-                &Patch::Apply(&Secret {
-                    metadata: ObjectMeta {
-                        name: secret_name.clone().into(),
+        let result = async {
+            let existing = secrets.get_opt(&secret_name).await?;
+            secrets
+                .patch(
+                    &secret_name,
+                    &PatchParams::apply("external-postgres.wafflehacks.cloud").force(),
+                    &Patch::Apply(&Secret {
+                        metadata: ObjectMeta {
+                            name: secret_name.clone().into(),
+                            labels: Some(secrets::labels(object, &name, &operator.0.cluster)),
+                            annotations: Some(object.spec.secret.annotations.clone()),
+                            ..Default::default()
+                        },
+                        data: Some(data.clone()),
+                        type_: object.spec.secret.type_.clone(),
                         ..Default::default()
-                    },
-                    string_data: secret_data.clone().into(),
-                    ..Default::default()
-                }),
-            )
-            .await?;
+                    }),
+                )
+                .await?;
 
-        info!(%namespace, "added secret to namespace");
+            // Whether the secret was created or its contents changed
+            let changed = match existing {
+                Some(secret) => secret.data.unwrap_or_default() != data,
+                None => true,
+            };
+            Ok::<_, kube::Error>(changed)
+        }
+        .await;
+        let changed = match result {
+            Ok(changed) => changed,
+            Err(error) => {
+                status.set(
+                    ConditionType::SecretsSynced,
+                    false,
+                    "WriteFailed",
+                    format!("failed to write secret to namespace {namespace}: {error}"),
+                );
+                return Err(error.into());
+            }
+        };
+
+        info!(%namespace, %changed, "added secret to namespace");
+        secret_namespaces.push(namespace.clone());
+        if changed {
+            replicated.push(namespace.clone());
+        }
     }
 
-    Ok(Action::await_change())
+    if !replicated.is_empty() {
+        let note = format!(
+            "wrote secret {secret_name} to namespaces: {}",
+            replicated.join(", ")
+        );
+        events.publish(object, Reason::SecretReplicated, note).await;
+    }
+
+    // Remove secrets from namespaces that are no longer listed, or that have since been renamed
+    let removed = match secrets::prune(object, &secret_name, &secret_namespaces, client).await {
+        Ok(removed) => removed,
+        Err(error) => {
+            status.set(
+                ConditionType::SecretsSynced,
+                false,
+                "PruneFailed",
+                format!("failed to remove stale secrets: {error}"),
+            );
+            return Err(error);
+        }
+    };
+    if !removed.is_empty() {
+        let note = format!("removed stale secrets: {}", removed.join(", "));
+        events.publish(object, Reason::SecretPruned, note).await;
+    }
+
+    status.set(ConditionType::SecretsSynced, true, "Synced", "");
+    status.succeeded(secret_namespaces, drift::hash(&digest_key, &secret_data));
+
+    // Come back to check for drift, or sooner if the generated password is due to be rotated
+    match (&object.spec.rotation, status.last_rotation_time()) {
+        (Some(rotation), Some(last)) => rotation.requeue(last, resync_interval),
+        _ => Ok(Action::requeue(resync_interval)),
+    }
+}
+
+/// Record a reconcile failure in the object's status
+#[instrument(skip_all, fields(name = %object.name_any()))]
+async fn report_failure(object: Arc<Database>, message: String, transient: bool, client: Client) {
+    let mut status = DatabaseStatus::from_object(&object);
+    status.failed(&message, transient);
+
+    if let Err(error) = status.patch(&object, client).await {
+        warn!(%error, "failed to update status");
+    }
 }
 
 /// Retrieve the password from the database spec
 #[instrument(skip_all)]
-async fn password_from_spec(object: &Database, client: Client) -> Result<String> {
+async fn password_from_spec(
+    object: &Database,
+    client: Client,
+    status: &mut DatabaseStatus,
+) -> Result<String> {
     match &object.spec.password {
         DatabasePassword::Value(v) => Ok(v.clone()),
+        DatabasePassword::Generate(spec) => {
+            let mut generated = password::generated(object, spec, client.clone()).await?;
+
+            let scheduled = match &object.spec.rotation {
+                Some(rotation) => rotation.due(generated.rotated_at)?,
+                None => false,
+            };
+            if scheduled || rotation::requested(object, generated.rotated_at) {
+                generated = password::rotate(object, spec, client).await?;
+                info!(%scheduled, "rotated password");
+            }
+
+            status.rotated(generated.rotated_at);
+            Ok(generated.password)
+        }
         DatabasePassword::FromSecret(spec) => {
             let secrets = Api::<Secret>::namespaced(client, &spec.namespace);
             let secret = secrets.get(&spec.name).await.map_err(|e| match e {
@@ -312,42 +1019,136 @@ async fn password_from_spec(object: &Database, client: Client) -> Result<String>
                 .remove(&spec.key)
                 .ok_or(Error::NoPassword)?;
             info!(key = %spec.key, "found key in secret");
-            let password =
-                String::from_utf8(password_bytes.0).map_err(|_| Error::InvalidPassword)?;
-            debug!(%password);
+            String::from_utf8(password_bytes.0).map_err(|_| Error::InvalidPassword)
+        }
+    }
+}
+
+/// Check whether the database's password is sourced from the secret
+fn password_references_secret(object: &Database, secret: &Secret, default_namespace: &str) -> bool {
+    let namespace = secret.namespace().unwrap_or_default();
+    let name = secret.name_any();
 
-            Ok(password)
+    match &object.spec.password {
+        DatabasePassword::Value(_) => false,
+        DatabasePassword::FromSecret(spec) => spec.namespace == namespace && spec.name == name,
+        DatabasePassword::Generate(spec) => {
+            spec.namespace(default_namespace) == namespace && password::secret_name(object) == name
         }
     }
 }
 
 /// Cleanup databases from the CRD
 #[instrument(skip_all)]
-async fn cleanup(object: Arc<Database>, databases: Databases, client: Client) -> Result<Action> {
-    let name = name_for_database(&object)?;
-    databases
-        .remove(&name, object.spec.retain_on_delete)
-        .await?;
-
-    let secret_name = secret_name_for_database(&object);
-    for namespace in &object.spec.secret.namespaces {
-        let secrets = Api::<Secret>::namespaced(client.clone(), namespace);
-        secrets
-            .delete(&secret_name, &DeleteParams::default())
-            .await?;
-
-        info!(%namespace, "removed secret from namespace");
+async fn cleanup(
+    object: Arc<Database>,
+    operator: Operator,
+    client: Client,
+    events: Events,
+) -> Result<Action> {
+    // Remove what was actually provisioned, even if the name in the spec has since changed
+    let provisioned = object.status.as_ref().and_then(|s| s.database_name());
+    let name = match provisioned {
+        Some(name) => name.to_string(),
+        None => name_for_database(&object)?,
+    };
+    let retain = object.spec.retain_on_delete;
+    let cluster = Some(operator.0.cluster.as_str());
+    match operator
+        .0
+        .databases
+        .remove(&name, Some(retain), cluster)
+        .await
+    {
+        Ok(()) if retain => {
+            let note = format!("removed user {name} and retained database");
+            events.publish(&object, Reason::Retained, note).await;
+        }
+        Ok(()) => {
+            let note = format!("dropped database and user {name}");
+            events.publish(&object, Reason::Dropped, note).await;
+        }
+        Err(database::Error::NotManaged) => warn!("database was not managed, skipping removal"),
+        Err(database::Error::ClaimedByCluster(owner)) => {
+            warn!(%owner, "database is managed by another cluster, skipping removal")
+        }
+        Err(database::Error::ManagedByApi) => {
+            warn!("database is managed through the api, skipping removal")
+        }
+        Err(error) => {
+            let note = format!("failed to remove database {name}: {error}");
+            events.publish(&object, Reason::DropFailed, note).await;
+            return Err(error.into());
+        }
     }
 
+    secrets::remove_all(&object, client).await?;
+
     Ok(Action::await_change())
 }
 
+/// The name of the database in PostgreSQL
 fn name_for_database(database: &Database) -> Result<String> {
-    database.metadata.name.clone().ok_or(Error::NoName)
+    if let Some(name) = &database.spec.database_name {
+        return Ok(name.clone());
+    }
+
+    let provisioned = database.status.as_ref().and_then(|s| s.database_name());
+    match provisioned {
+        Some(name) => Ok(name.to_string()),
+        None => sanitized_name(database),
+    }
+}
+
+/// The name of the role that owns the database in PostgreSQL
+fn username_for_database(database: &Database) -> Result<String> {
+    if let Some(username) = &database.spec.username {
+        return Ok(username.clone());
+    }
+
+    let provisioned = database.status.as_ref().and_then(|s| s.username());
+    match provisioned {
+        Some(username) => Ok(username.to_string()),
+        None => sanitized_name(database),
+    }
+}
+
+/// Convert the resource's name into an unquoted PostgreSQL identifier. Characters other than
+/// lowercase letters, digits and underscores are replaced with underscores. Names that had to be
+/// changed or are too long get a hash suffix, so different resources never share an identifier,
+/// i.e. `a-b` and `a.b`.
+fn sanitized_name(database: &Database) -> Result<String> {
+    let name = database.metadata.name.as_ref().ok_or(Error::NoName)?;
+    Ok(sanitize(name))
+}
+
+fn sanitize(name: &str) -> String {
+    let mut sanitized = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '_' => c,
+            'A'..='Z' => c.to_ascii_lowercase(),
+            _ => '_',
+        })
+        .collect::<String>();
+    if sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+
+    if sanitized != name || sanitized.len() > 63 {
+        // 32-bit FNV-1a of the original name
+        let hash = name.bytes().fold(0x811c9dc5u32, |hash, byte| {
+            (hash ^ u32::from(byte)).wrapping_mul(0x01000193)
+        });
+        sanitized.truncate(54);
+        sanitized.push_str(&format!("_{hash:08x}"));
+    }
+
+    sanitized
 }
 
 fn secret_name_for_database(database: &Database) -> String {
-    let name = name_for_database(database).unwrap();
+    let name = database.name_any();
     database
         .spec
         .secret
@@ -382,16 +1183,32 @@ async fn apply_crd(client: Client) -> Result<()> {
     singular = "database",
     plural = "databases",
     shortname = "db",
-    shortname = "dbs"
+    shortname = "dbs",
+    status = "DatabaseStatus",
+    printcolumn = r#"{"name":"Ready", "type":"string", "jsonPath":".status.conditions[?(@.type==\"Ready\")].status"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#,
+    printcolumn = r#"{"name":"Error", "type":"string", "priority":1, "jsonPath":".status.lastError"}"#
 )]
 #[serde(rename_all = "camelCase")]
 struct DatabaseSpec {
-    /// The password for the database
-    #[validate(length(min = 1))]
+    /// The name of the database in PostgreSQL, defaults to the resource's name with invalid characters
+    /// replaced by underscores and a hash suffix added. Cannot be changed once set.
+    #[serde(default)]
+    #[schemars(schema_with = "immutable_name")]
+    database_name: Option<String>,
+    /// The name of the role that owns the database, defaults to the resource's name with invalid
+    /// characters replaced by underscores and a hash suffix added. Cannot be changed once set.
+    #[serde(default)]
+    #[schemars(schema_with = "immutable_name")]
+    username: Option<String>,
+    /// The password for the database, generated if not specified
+    #[serde(default)]
     password: DatabasePassword,
     /// Whether to retain the database's data on deletion
     #[serde(default)]
     retain_on_delete: bool,
+    /// When to rotate the generated password
+    rotation: Option<DatabaseRotation>,
     /// Specification for the connection secret
     secret: DatabaseSecret,
 }
@@ -401,6 +1218,13 @@ struct DatabaseSpec {
 enum DatabasePassword {
     Value(#[validate(length(min = 1))] String),
     FromSecret(DatabasePasswordSecret),
+    Generate(DatabasePasswordGenerate),
+}
+
+impl Default for DatabasePassword {
+    fn default() -> Self {
+        Self::Generate(DatabasePasswordGenerate::default())
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
@@ -414,6 +1238,89 @@ struct DatabasePasswordSecret {
     namespace: String,
 }
 
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DatabasePasswordGenerate {
+    /// The number of characters in the password
+    #[serde(default = "DatabasePasswordGenerate::default_length")]
+    #[validate(range(min = 16, max = 256))]
+    length: u16,
+    /// The set of characters to generate the password from
+    #[serde(default)]
+    charset: PasswordCharset,
+    /// The namespace to store the generated password in, defaults to the operator's own namespace
+    #[validate(length(min = 1))]
+    namespace: Option<String>,
+}
+
+impl DatabasePasswordGenerate {
+    fn default_length() -> u16 {
+        32
+    }
+
+    /// The namespace the generated password is stored in, falling back to the operator's own
+    fn namespace<'a>(&'a self, default: &'a str) -> &'a str {
+        self.namespace.as_deref().unwrap_or(default)
+    }
+}
+
+impl Default for DatabasePasswordGenerate {
+    fn default() -> Self {
+        Self {
+            length: Self::default_length(),
+            charset: PasswordCharset::default(),
+            namespace: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum PasswordCharset {
+    /// Upper and lowercase letters, and digits
+    #[default]
+    Alphanumeric,
+    /// Lowercase hexadecimal digits
+    Hex,
+    /// Any printable ASCII character, excluding spaces, quotes, and backslashes
+    Symbols,
+}
+
+impl PasswordCharset {
+    fn characters(&self) -> &'static [u8] {
+        match self {
+            Self::Alphanumeric => b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
+            Self::Hex => b"0123456789abcdef",
+            Self::Symbols => b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789!#$%&()*+,-./:;<=>?@[]^_{|}~",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DatabaseRotation {
+    /// How often to rotate the password, i.e. 30d or 12h
+    #[validate(length(min = 1))]
+    interval: Option<String>,
+    /// A cron expression for when to rotate the password, i.e. "0 4 * * SUN"
+    #[validate(length(min = 1))]
+    schedule: Option<String>,
+}
+
+/// A built-in set of keys for the connection secret
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum SecretPreset {
+    /// The libpq environment variables (PGHOST, PGPORT, etc.) and a postgresql:// DATABASE_URL
+    Libpq,
+    /// A DATABASE_URL using the postgres:// scheme
+    PostgresUrl,
+    /// A JDBC_URL, JDBC_USERNAME, and JDBC_PASSWORD
+    Jdbc,
+    /// Django-style DATABASE_HOST, DATABASE_NAME, DATABASE_USER, etc.
+    Django,
+}
+
 #[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct DatabaseSecret {
@@ -423,6 +1330,51 @@ struct DatabaseSecret {
     /// The namespaces to replicate the secret to
     #[serde(default)]
     namespaces: Vec<String>,
+    /// Also replicate the secret to every namespace matching the selector, removing it from
+    /// namespaces that stop matching
+    namespace_selector: Option<LabelSelector>,
+    /// The built-in sets of keys to include in the secret, defaults to libpq
+    #[serde(default = "DatabaseSecret::default_presets")]
+    presets: Vec<SecretPreset>,
+    /// Additional keys to include in the secret, mapped to templates that can reference {host},
+    /// {port}, {user}, {password}, {database}, {sslmode}, and the percent-encoded connection URLs
+    /// {url}, {postgres_url}, and {jdbc_url}. Replaces any keys from the presets.
+    #[serde(default)]
+    templates: BTreeMap<String, String>,
+    /// Extra libpq parameters to add to the query string of the connection URLs, i.e.
+    /// application_name or connect_timeout
+    #[serde(default)]
+    url_parameters: BTreeMap<String, String>,
+    /// Additional labels to add to the secret. The labels set by the operator take precedence.
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    /// Annotations to add to the secret
+    #[serde(default)]
+    annotations: BTreeMap<String, String>,
+    /// The type of the secret, defaults to Opaque. The type of an existing secret cannot be
+    /// changed, so it must be deleted for a new type to take effect.
+    #[serde(rename = "type")]
+    #[validate(length(min = 1))]
+    type_: Option<String>,
+}
+
+impl DatabaseSecret {
+    fn default_presets() -> Vec<SecretPreset> {
+        vec![SecretPreset::Libpq]
+    }
+}
+
+/// The schema for a PostgreSQL identifier that cannot be changed once set
+fn immutable_name(gen: &mut SchemaGenerator) -> Schema {
+    let mut schema = gen.subschema_for::<String>().into_object();
+    schema.string().min_length = Some(1);
+    schema.string().max_length = Some(63);
+    schema.extensions.insert(
+        String::from("x-kubernetes-validations"),
+        json!([{ "rule": "self == oldSelf", "message": "field is immutable" }]),
+    );
+
+    schema.into()
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
@@ -431,14 +1383,140 @@ type Result<T, E = Error> = std::result::Result<T, E>;
 pub enum Error {
     #[error("resource does not have a name")]
     NoName,
+    #[error("{0} cannot be changed once the database is provisioned")]
+    ImmutableName(&'static str),
     #[error("could not find the password")]
     NoPassword,
     #[error("invalid password sequence, likely invalid utf-8")]
     InvalidPassword,
+    #[error("invalid password rotation: {0}")]
+    InvalidRotation(String),
+    #[error("invalid secret template for {0}")]
+    InvalidTemplate(String),
+    #[error("the operator is not running")]
+    NotRunning,
+    #[error("no database resource exists for the database")]
+    NotFound,
+    #[error("the database's password is not generated")]
+    NotGenerated,
+    #[error("context {0:?} does not exist in the kubeconfig")]
+    UnknownContext(String),
+    #[error("no cluster named {0:?} is configured")]
+    UnknownCluster(String),
     #[error(transparent)]
     Database(#[from] database::Error),
     #[error(transparent)]
     Kubernetes(#[from] kube::Error),
     #[error(transparent)]
+    Kubeconfig(#[from] KubeconfigError),
+    #[error(transparent)]
+    InCluster(#[from] InClusterError),
+    #[error(transparent)]
     Wait(#[from] wait::Error),
 }
+
+impl Error {
+    /// Whether retrying the reconcile could succeed without the spec or environment changing
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::NoName
+            | Self::ImmutableName(_)
+            | Self::NoPassword
+            | Self::InvalidPassword
+            | Self::InvalidRotation(_)
+            | Self::InvalidTemplate(_)
+            | Self::NotRunning
+            | Self::NotFound
+            | Self::NotGenerated
+            | Self::UnknownContext(_)
+            | Self::UnknownCluster(_)
+            | Self::Kubeconfig(_)
+            | Self::InCluster(_) => false,
+            Self::Database(error) => error.is_transient(),
+            Self::Kubernetes(error) => is_transient_kube_error(error),
+            Self::Wait(_) => true,
+        }
+    }
+}
+
+/// Expand a leading `~` in the path to the user's home directory
+fn expand_tilde(path: &Path) -> PathBuf {
+    shellexpand::tilde(&path.as_os_str().to_string_lossy())
+        .to_string()
+        .into()
+}
+
+/// Whether the Kubernetes error could go away by retrying. Client errors other than conflicts and
+/// rate limiting will not.
+fn is_transient_kube_error(error: &kube::Error) -> bool {
+    match error {
+        kube::Error::Api(response) => {
+            !(400..500).contains(&response.code) || matches!(response.code, 409 | 429)
+        }
+        _ => true,
+    }
+}
+
+/// Whether the reconcile failure could succeed when retried
+fn is_transient(error: &finalizer::Error<Error>) -> bool {
+    match error {
+        finalizer::Error::ApplyFailed(error) | finalizer::Error::CleanupFailed(error) => {
+            error.is_transient()
+        }
+        finalizer::Error::AddFinalizer(error) | finalizer::Error::RemoveFinalizer(error) => {
+            is_transient_kube_error(error)
+        }
+        finalizer::Error::UnnamedObject => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sanitize;
+
+    #[test]
+    fn sanitize_valid_name() {
+        assert_eq!(sanitize("app"), "app");
+        assert_eq!(sanitize("app2"), "app2");
+    }
+
+    #[test]
+    fn sanitize_replaced_characters() {
+        assert_eq!(sanitize("my-app"), "my_app_ef484009");
+        assert_eq!(sanitize("my.app"), "my_app_619d1e02");
+    }
+
+    #[test]
+    fn sanitize_does_not_collide() {
+        assert_ne!(sanitize("a-b"), sanitize("a.b"));
+        assert_ne!(sanitize("a-b"), sanitize("a_b"));
+    }
+
+    #[test]
+    fn sanitize_leading_digit() {
+        let sanitized = sanitize("1app");
+        assert!(sanitized.starts_with("_1app_"));
+        assert_eq!(sanitized.len(), 14);
+    }
+
+    #[test]
+    fn sanitize_long_name() {
+        let name = "a".repeat(100);
+        let sanitized = sanitize(&name);
+        assert_eq!(sanitized.len(), 63);
+        assert!(sanitized.starts_with(&"a".repeat(54)));
+        assert_ne!(sanitized, sanitize(&"a".repeat(101)));
+    }
+
+    #[test]
+    fn sanitize_is_a_valid_identifier() {
+        for name in ["my-app", "My.App", "1app", "x".repeat(253).as_str()] {
+            let sanitized = sanitize(name);
+            assert!(sanitized.len() <= 63);
+            assert!(!sanitized.starts_with(|c: char| c.is_ascii_digit()));
+            assert!(sanitized
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'));
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use super::Result;
+use crate::server::database::{Credentials, Databases};
+use clap::Args;
+use k8s_openapi::{api::core::v1::Secret, apimachinery::pkg::apis::meta::v1::ObjectMeta};
+use kube::{
+    api::{Patch, PatchParams},
+    client::Client,
+    Api,
+};
+use std::{collections::BTreeMap, time::Duration};
+use tokio::{sync::watch, time};
+use tracing::{error, info, instrument};
+
+/// How long to wait before retrying after failing to write the secret
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, Args)]
+#[group(skip)]
+pub struct Options {
+    /// The name of the secret to write the credentials for PgBouncer's auth user to. Unless an auth
+    /// file is configured, the leader of the primary cluster (the first by name) generates and
+    /// rotates the auth user's password, adopting the credentials already in the secret.
+    #[arg(long = "kube-auth-secret-name", env = "KUBE_AUTH_SECRET_NAME")]
+    pub name: Option<String>,
+
+    /// The namespace to write the auth user's credentials secret to
+    #[arg(
+        long = "kube-auth-secret-namespace",
+        default_value = "default",
+        env = "KUBE_AUTH_SECRET_NAMESPACE"
+    )]
+    pub namespace: String,
+}
+
+/// Keep the auth user's credentials secret up-to-date until the credentials channel closes
+#[instrument(skip_all, fields(name = ?opts.name, namespace = %opts.namespace))]
+pub async fn sync(
+    client: Client,
+    opts: Options,
+    mut credentials: watch::Receiver<Option<Credentials>>,
+) {
+    let Some(name) = opts.name else {
+        return;
+    };
+    let secrets = Api::<Secret>::namespaced(client, &opts.namespace);
+
+    loop {
+        // Nothing can be written until the credentials are known
+        let Some(current) = credentials.borrow_and_update().clone() else {
+            if credentials.changed().await.is_err() {
+                return;
+            }
+            continue;
+        };
+
+        match write(&secrets, &name, &current).await {
+            Ok(()) => {
+                if credentials.changed().await.is_err() {
+                    return;
+                }
+            }
+            Err(error) => {
+                error!(%error, "failed to write auth user secret");
+                tokio::select! {
+                    changed = credentials.changed() => if changed.is_err() { return },
+                    _ = time::sleep(RETRY_DELAY) => {},
+                }
+            }
+        }
+    }
+}
+
+/// Take over the auth user's password while leading, adopting the credentials already in the secret
+/// so a change of leader does not invalidate them, then rotate it periodically
+#[instrument(skip_all, fields(name = ?opts.name, namespace = %opts.namespace))]
+pub async fn manage(client: Client, opts: Options, databases: Databases) {
+    let Some(name) = opts.name else {
+        return;
+    };
+    let secrets = Api::<Secret>::namespaced(client, &opts.namespace);
+
+    while let Err(error) = take_over(&secrets, &name, &databases).await {
+        error!(%error, "failed to take over auth user password");
+        time::sleep(RETRY_DELAY).await;
+    }
+
+    if let Some(interval) = databases.auth_rotation_interval() {
+        databases.rotate_auth_password_every(interval).await;
+    }
+}
+
+/// Use the credentials from the secret if it has any, otherwise generate new ones unless the auth
+/// user was just created
+async fn take_over(secrets: &Api<Secret>, name: &str, databases: &Databases) -> Result<()> {
+    let current = databases.auth_credentials().borrow().clone();
+
+    match read(secrets, name, databases.auth_user()).await? {
+        Some(published) if current.as_ref() == Some(&published) => {}
+        Some(published) => databases.adopt_auth_credentials(published).await?,
+        None if current.is_some() => {}
+        None => databases.rotate_auth_password().await?,
+    }
+
+    Ok(())
+}
+
+/// Read the auth user's credentials from the secret, if it exists and contains them
+async fn read(secrets: &Api<Secret>, name: &str, username: &str) -> Result<Option<Credentials>> {
+    let Some(secret) = secrets.get_opt(name).await? else {
+        return Ok(None);
+    };
+    let data = secret.data.unwrap_or_default();
+    let value = |key: &str| {
+        data.get(key)
+            .and_then(|value| String::from_utf8(value.0.clone()).ok())
+    };
+
+    let credentials = match (value("username"), value("password")) {
+        (Some(published), Some(password)) if published == username && !password.is_empty() => {
+            Some(Credentials {
+                username: published,
+                password,
+            })
+        }
+        _ => None,
+    };
+    Ok(credentials)
+}
+
+/// Write the credentials to the secret
+async fn write(secrets: &Api<Secret>, name: &str, credentials: &Credentials) -> Result<()> {
+    let data = BTreeMap::from([
+        (String::from("username"), credentials.username.clone()),
+        (String::from("password"), credentials.password.clone()),
+        (String::from("userlist.txt"), credentials.userlist()),
+    ]);
+
+    secrets
+        .patch(
+            name,
+            &PatchParams::apply("external-postgres.wafflehacks.cloud").force(),
+            &Patch::Apply(&Secret {
+                metadata: ObjectMeta {
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                },
+                string_data: Some(data),
+                ..Default::default()
+            }),
+        )
+        .await?;
+    info!("wrote auth user secret");
+
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use super::Database;
+use kube::runtime::reflector::ObjectRef;
+use parking_lot::Mutex;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+/// The delay before the first retry of a failed reconcile
+const INITIAL_DELAY: Duration = Duration::from_secs(5);
+
+/// Tracks consecutive reconcile failures for each object to retry them with exponential backoff
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    max: Duration,
+    failures: Arc<Mutex<HashMap<ObjectRef<Database>, u32>>>,
+}
+
+impl Backoff {
+    pub fn new(max: Duration) -> Self {
+        Self {
+            max,
+            failures: Arc::default(),
+        }
+    }
+
+    /// Record a failure for the object, returning how long to wait before retrying
+    pub fn failed(&self, object: ObjectRef<Database>) -> Duration {
+        let failures = {
+            let mut failures = self.failures.lock();
+            let count = failures.entry(object).or_default();
+            *count = count.saturating_add(1);
+            *count
+        };
+
+        INITIAL_DELAY
+            .checked_mul(2u32.saturating_pow(failures - 1))
+            .unwrap_or(self.max)
+            .min(self.max)
+    }
+
+    /// Forget any failures for the object after it was reconciled successfully
+    pub fn succeeded(&self, object: &ObjectRef<Database>) {
+        let mut failures = self.failures.lock();
+        failures.remove(object);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backoff, Database, INITIAL_DELAY};
+    use kube::runtime::reflector::ObjectRef;
+    use std::time::Duration;
+
+    fn object(name: &str) -> ObjectRef<Database> {
+        ObjectRef::new(name)
+    }
+
+    #[test]
+    fn doubles_after_each_failure() {
+        let backoff = Backoff::new(Duration::from_secs(300));
+        let delays = (0..4)
+            .map(|_| backoff.failed(object("app")))
+            .collect::<Vec<_>>();
+
+        assert_eq!(delays, [5, 10, 20, 40].map(Duration::from_secs).to_vec());
+    }
+
+    #[test]
+    fn capped_at_max() {
+        let max = Duration::from_secs(60);
+        let backoff = Backoff::new(max);
+        for _ in 0..100 {
+            assert!(backoff.failed(object("app")) <= max);
+        }
+        assert_eq!(backoff.failed(object("app")), max);
+    }
+
+    #[test]
+    fn reset_after_success() {
+        let backoff = Backoff::new(Duration::from_secs(300));
+        backoff.failed(object("app"));
+        backoff.failed(object("app"));
+
+        backoff.succeeded(&object("app"));
+        assert_eq!(backoff.failed(object("app")), INITIAL_DELAY);
+    }
+
+    #[test]
+    fn tracked_per_object() {
+        let backoff = Backoff::new(Duration::from_secs(300));
+        backoff.failed(object("app"));
+        backoff.failed(object("app"));
+
+        assert_eq!(backoff.failed(object("other")), INITIAL_DELAY);
+        assert_eq!(backoff.failed(object("app")), Duration::from_secs(20));
+    }
+}
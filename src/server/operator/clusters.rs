@@ -0,0 +1,243 @@
+use super::{
+    AuthSecretOptions, ConfigSource, ConnectionInfo, ControllerOptions, Error as OperatorError,
+    Operator, Result as OperatorResult,
+};
+use crate::{models::operator::StateResponse, server::database::Databases};
+use clap::Args;
+use serde::Deserialize;
+use sqlx::postgres::PgSslMode;
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tracing::{info, instrument};
+
+#[derive(Clone, Debug, Args)]
+#[group(skip)]
+pub struct Options {
+    /// The name of the cluster when only one is configured, recorded as the owner of its databases
+    #[arg(
+        long = "kube-cluster-name",
+        default_value = "default",
+        env = "KUBE_CLUSTER_NAME"
+    )]
+    pub cluster_name: String,
+
+    /// A YAML file mapping cluster names to their kubeconfig, context, and connection info. Any
+    /// settings omitted for a cluster default to the corresponding flags.
+    #[arg(long = "kube-clusters-file", env = "KUBE_CLUSTERS_FILE")]
+    pub clusters_file: Option<PathBuf>,
+}
+
+/// How to connect to a cluster and how its clients should connect to the database
+#[derive(Clone, Debug)]
+pub struct Cluster {
+    pub name: String,
+    pub kubeconfig: PathBuf,
+    pub context: Option<String>,
+    pub connection_info: ConnectionInfo,
+}
+
+/// A cluster's entry in the clusters file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+struct ClusterEntry {
+    kubeconfig: Option<PathBuf>,
+    context: Option<String>,
+    database_host: Option<String>,
+    database_port: Option<u16>,
+    database_ssl_mode: Option<String>,
+}
+
+impl ClusterEntry {
+    /// Fill in any missing settings from the defaults
+    fn into_cluster(self, name: String, defaults: &Cluster) -> Result<Cluster> {
+        let sslmode = match self.database_ssl_mode {
+            Some(mode) => mode
+                .parse::<PgSslMode>()
+                .map_err(|_| Error::InvalidSslMode(name.clone(), mode))?,
+            None => defaults.connection_info.sslmode,
+        };
+
+        Ok(Cluster {
+            kubeconfig: self
+                .kubeconfig
+                .unwrap_or_else(|| defaults.kubeconfig.clone()),
+            context: self.context.or_else(|| defaults.context.clone()),
+            connection_info: ConnectionInfo {
+                remote_host: self
+                    .database_host
+                    .unwrap_or_else(|| defaults.connection_info.remote_host.clone()),
+                remote_port: self
+                    .database_port
+                    .unwrap_or(defaults.connection_info.remote_port),
+                sslmode,
+            },
+            name,
+        })
+    }
+}
+
+/// The operators for every configured cluster
+#[derive(Clone, Debug)]
+pub struct Clusters {
+    operators: Arc<BTreeMap<String, Operator>>,
+    /// The cluster used when none is specified
+    default: Arc<str>,
+}
+
+impl Clusters {
+    /// Create an operator for each cluster, either from the clusters file or the single cluster
+    /// described by `defaults`
+    #[instrument(name = "clusters", skip_all)]
+    pub fn new(
+        opts: &Options,
+        defaults: Cluster,
+        config_source: ConfigSource,
+        auth_secret: AuthSecretOptions,
+        controller: ControllerOptions,
+        databases: Databases,
+    ) -> Result<Self> {
+        let clusters = match &opts.clusters_file {
+            Some(path) => load(path, &defaults)?,
+            None => {
+                let name = opts.cluster_name.clone();
+                validate_name(&name)?;
+                vec![Cluster { name, ..defaults }]
+            }
+        };
+        info!(count = clusters.len(), "loaded cluster configuration");
+
+        // Without an auth file, the auth user's password is managed by the leader of the primary
+        // cluster, the first by name, so only a single instance ever changes it
+        let manage_auth_password = auth_secret.name.is_some() && !databases.manages_auth_password();
+
+        // The only cluster is always the default, otherwise it must be picked by name
+        let default = match clusters.as_slice() {
+            [cluster] => cluster.name.as_str().into(),
+            _ => opts.cluster_name.as_str().into(),
+        };
+
+        let operators = clusters
+            .into_iter()
+            .enumerate()
+            .map(|(i, cluster)| {
+                let name = cluster.name.clone();
+                let operator = Operator::new(
+                    cluster,
+                    config_source,
+                    auth_secret.clone(),
+                    manage_auth_password && i == 0,
+                    controller.clone(),
+                    databases.clone(),
+                );
+                (name, operator)
+            })
+            .collect();
+
+        Ok(Self {
+            operators: Arc::new(operators),
+            default,
+        })
+    }
+
+    /// Get the operator for a cluster
+    pub fn get(&self, cluster: &str) -> OperatorResult<&Operator> {
+        self.operators
+            .get(cluster)
+            .ok_or_else(|| OperatorError::UnknownCluster(cluster.to_string()))
+    }
+
+    /// Get the operator for the only configured cluster, or the one named by `--kube-cluster-name`
+    pub fn default(&self) -> OperatorResult<&Operator> {
+        self.get(&self.default)
+    }
+
+    /// Get the state of every cluster's controller
+    pub fn states(&self) -> Vec<StateResponse> {
+        self.operators.values().map(Operator::state).collect()
+    }
+
+    /// Request the generated password for a database be rotated by the cluster that owns it. If
+    /// the owner was never recorded, every cluster is checked for the database.
+    #[instrument(skip(self))]
+    pub async fn request_rotation(
+        &self,
+        database: &str,
+        cluster: Option<&str>,
+    ) -> OperatorResult<()> {
+        if let Some(cluster) = cluster {
+            return self.get(cluster)?.request_rotation(database).await;
+        }
+
+        let mut result = Err(OperatorError::NotFound);
+        for operator in self.operators.values() {
+            match operator.request_rotation(database).await {
+                Err(OperatorError::NotFound) => {}
+                Err(OperatorError::NotRunning) => result = Err(OperatorError::NotRunning),
+                other => return other,
+            }
+        }
+
+        result
+    }
+
+    /// Stop every cluster's controller
+    pub async fn stop(&self) {
+        for operator in self.operators.values() {
+            operator.stop().await;
+        }
+    }
+}
+
+/// Load the clusters from the file in order of their names, filling in any missing settings from
+/// the defaults
+fn load(path: &Path, defaults: &Cluster) -> Result<Vec<Cluster>> {
+    let contents = fs::read_to_string(path)?;
+    let entries: BTreeMap<String, ClusterEntry> = serde_yaml::from_str(&contents)?;
+    if entries.is_empty() {
+        return Err(Error::Empty);
+    }
+
+    entries
+        .into_iter()
+        .map(|(name, entry)| {
+            validate_name(&name)?;
+            entry.into_cluster(name, defaults)
+        })
+        .collect()
+}
+
+/// Cluster names are used in URLs and recorded as the owner of databases, so they are restricted
+/// to lowercase alphanumeric characters and dashes
+fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 63
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidName(name.to_string()))
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read clusters file: {0}")]
+    Read(#[from] io::Error),
+    #[error("failed to parse clusters file: {0}")]
+    Parse(#[from] serde_yaml::Error),
+    #[error("no clusters are defined in the clusters file")]
+    Empty,
+    #[error("invalid cluster name {0:?}, must be lowercase alphanumeric characters or dashes")]
+    InvalidName(String),
+    #[error("invalid ssl mode {1:?} for cluster {0:?}")]
+    InvalidSslMode(String, String),
+}
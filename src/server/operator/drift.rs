@@ -0,0 +1,146 @@
+use super::{status::DatabaseStatus, Result};
+use crate::server::database::Databases;
+use hmac::{Hmac, Mac};
+use k8s_openapi::api::core::v1::Secret;
+use kube::{client::Client, Api};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use tracing::{instrument, warn};
+
+/// Compare the live state of a provisioned database and its connection secrets against the desired
+/// state, returning a description of each difference
+#[instrument(skip_all)]
+pub async fn detect(
+    databases: &Databases,
+    namespaces: &[String],
+    secret_name: &str,
+    secret_data: &BTreeMap<String, String>,
+    client: Client,
+    status: &DatabaseStatus,
+) -> Result<Vec<String>> {
+    let (Some(name), Some(username)) = (status.database_name(), status.username()) else {
+        // Nothing can have drifted if the database was never provisioned
+        return Ok(Vec::new());
+    };
+
+    let mut drift = databases
+        .drift(name, username)
+        .await?
+        .into_iter()
+        .map(|drift| drift.to_string())
+        .collect::<Vec<_>>();
+
+    // Secrets are only checked if their contents are expected to be unchanged since they were last
+    // written, otherwise a changed password would be reported as drift
+    let key = databases.digest_key();
+    if status.secret_data_hash() == Some(hash(&key, secret_data).as_str()) {
+        for namespace in status.secret_namespaces() {
+            if !namespaces.contains(namespace) {
+                continue;
+            }
+
+            let secrets = Api::<Secret>::namespaced(client.clone(), namespace);
+            match secrets.get_opt(secret_name).await? {
+                None => drift.push(format!("secret is missing from namespace {namespace}")),
+                Some(secret) if !matches(&secret, secret_data) => {
+                    drift.push(format!("secret in namespace {namespace} was modified"))
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    for drift in &drift {
+        warn!(%drift, "detected drift");
+    }
+
+    Ok(drift)
+}
+
+/// Check whether the secret contains the desired data, ignoring any extra keys
+fn matches(secret: &Secret, desired: &BTreeMap<String, String>) -> bool {
+    let data = secret.data.as_ref();
+    desired.iter().all(|(key, value)| {
+        data.and_then(|data| data.get(key))
+            .is_some_and(|actual| actual.0 == value.as_bytes())
+    })
+}
+
+/// Hash the secret data so changes to it can be detected without storing it in the status. The hash
+/// is keyed, as the data includes the password and the status can be read by anyone who can read
+/// the database resource.
+pub fn hash(key: &[u8], data: &BTreeMap<String, String>) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+
+    // Every key and value is prefixed with its length so different entries cannot collide
+    for (key, value) in data {
+        for part in [key, value] {
+            mac.update(&(part.len() as u64).to_be_bytes());
+            mac.update(part.as_bytes());
+        }
+    }
+
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Hash the password so changes to it can be detected without storing it in the status
+pub fn hash_password(key: &[u8], password: &str) -> String {
+    hash(
+        key,
+        &BTreeMap::from([(String::from("password"), password.to_string())]),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hash;
+    use std::collections::BTreeMap;
+
+    fn data(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn stable() {
+        let secret = data(&[("username", "app"), ("password", "secret")]);
+        assert_eq!(hash(b"key", &secret), hash(b"key", &secret));
+        assert_eq!(hash(b"key", &secret).len(), 64);
+    }
+
+    #[test]
+    fn depends_on_key() {
+        let secret = data(&[("password", "secret")]);
+        assert_ne!(hash(b"key", &secret), hash(b"other", &secret));
+    }
+
+    #[test]
+    fn detects_changes() {
+        let secret = data(&[("username", "app"), ("password", "secret")]);
+        let changed = data(&[("username", "app"), ("password", "secret2")]);
+        assert_ne!(hash(b"key", &secret), hash(b"key", &changed));
+    }
+
+    #[test]
+    fn entries_are_delimited() {
+        assert_ne!(
+            hash(b"key", &data(&[("a", "bc")])),
+            hash(b"key", &data(&[("ab", "c")]))
+        );
+    }
+
+    #[test]
+    fn known_value() {
+        // HMAC-SHA256 with the key "key" over the length-prefixed entry ("a", "b")
+        assert_eq!(
+            hash(b"key", &data(&[("a", "b")])),
+            "749b7f39129da523e75cf42649c029c28a9670a7e44a7df224fdda0b8b39d27b"
+        );
+    }
+}
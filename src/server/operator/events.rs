@@ -0,0 +1,144 @@
+use super::Database;
+use kube::{
+    client::Client,
+    runtime::events::{Event, EventType, Recorder, Reporter},
+    Resource, ResourceExt,
+};
+use parking_lot::Mutex;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{debug, instrument, warn};
+
+/// How long to suppress identical events for the same object
+const SUPPRESSION_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Identifies an event by the object's UID, the reason, and the note
+type EventKey = (String, Reason, String);
+
+/// Publishes Kubernetes events for database objects, suppressing identical events that occur
+/// within a short window of each other
+#[derive(Clone)]
+pub struct Events {
+    client: Client,
+    reporter: Reporter,
+    recent: Arc<Mutex<HashMap<EventKey, Instant>>>,
+}
+
+impl Events {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            reporter: Reporter {
+                controller: String::from("external-postgres"),
+                instance: None,
+            },
+            recent: Arc::default(),
+        }
+    }
+
+    /// Publish an event for the object
+    #[instrument(skip_all, fields(name = %object.name_any(), ?reason))]
+    pub async fn publish(&self, object: &Database, reason: Reason, note: impl Into<String>) {
+        let note = note.into();
+
+        let key = (
+            object.uid().unwrap_or_else(|| object.name_any()),
+            reason,
+            note.clone(),
+        );
+        if !self.should_publish(key) {
+            debug!("suppressed duplicate event");
+            return;
+        }
+
+        let recorder = Recorder::new(
+            self.client.clone(),
+            self.reporter.clone(),
+            object.object_ref(&()),
+        );
+        let event = Event {
+            type_: reason.type_(),
+            reason: format!("{reason:?}"),
+            note: Some(note),
+            action: reason.action().to_string(),
+            secondary: None,
+        };
+
+        // Events are informational, so failing to publish one should not fail the reconcile
+        if let Err(error) = recorder.publish(event).await {
+            warn!(%error, "failed to publish event");
+        }
+    }
+
+    /// Check whether an identical event was published recently, recording it if not
+    fn should_publish(&self, key: EventKey) -> bool {
+        let now = Instant::now();
+
+        let mut recent = self.recent.lock();
+        recent.retain(|_, published| now.duration_since(*published) < SUPPRESSION_WINDOW);
+
+        match recent.entry(key) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(now);
+                true
+            }
+        }
+    }
+}
+
+/// The reason an event was published
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Reason {
+    /// The database and its user were created
+    Created,
+    /// The password for an existing user was set
+    PasswordUpdated,
+    /// The connection secret was written to the namespaces
+    SecretReplicated,
+    /// Connection secrets that are no longer desired were removed
+    SecretPruned,
+    /// The secret containing the password could not be found
+    SecretMissing,
+    /// The database and its user were removed
+    Dropped,
+    /// The database's user was removed, but the database was kept
+    Retained,
+    /// The database could not be removed
+    DropFailed,
+    /// Reconciling the database failed
+    ReconcileFailed,
+    /// The live state differed from the desired state
+    DriftDetected,
+}
+
+impl Reason {
+    fn type_(&self) -> EventType {
+        match self {
+            Self::Created
+            | Self::PasswordUpdated
+            | Self::SecretReplicated
+            | Self::SecretPruned
+            | Self::Dropped
+            | Self::Retained => EventType::Normal,
+            Self::SecretMissing
+            | Self::DropFailed
+            | Self::ReconcileFailed
+            | Self::DriftDetected => EventType::Warning,
+        }
+    }
+
+    fn action(&self) -> &'static str {
+        match self {
+            Self::Created | Self::PasswordUpdated | Self::SecretMissing | Self::ReconcileFailed => {
+                "Reconcile"
+            }
+            Self::SecretReplicated | Self::SecretPruned => "ReplicateSecret",
+            Self::DriftDetected => "DetectDrift",
+            Self::Dropped | Self::Retained | Self::DropFailed => "Cleanup",
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use super::Result;
+use chrono::Utc;
+use clap::Args;
+use k8s_openapi::{
+    api::coordination::v1::{Lease, LeaseSpec},
+    apimachinery::pkg::apis::meta::v1::{MicroTime, ObjectMeta},
+};
+use kube::{api::PostParams, client::Client, Api};
+use std::time::Duration;
+use tokio::{sync::oneshot, time};
+use tracing::{debug, info, instrument, warn};
+use uuid::Uuid;
+
+#[derive(Clone, Debug, Args)]
+#[group(skip)]
+pub struct Options {
+    /// Only run the controller while holding a lease, allowing multiple replicas to run at once
+    #[arg(long = "kube-leader-election", env = "KUBE_LEADER_ELECTION")]
+    pub enabled: bool,
+
+    /// The name of the lease used for leader election
+    #[arg(
+        long = "kube-lease-name",
+        default_value = "external-postgres",
+        env = "KUBE_LEASE_NAME"
+    )]
+    pub lease_name: String,
+
+    /// The namespace of the lease used for leader election
+    #[arg(
+        long = "kube-lease-namespace",
+        default_value = "default",
+        env = "KUBE_LEASE_NAMESPACE"
+    )]
+    pub lease_namespace: String,
+
+    /// How long the lease is held for without being renewed
+    #[arg(
+        long = "kube-lease-duration",
+        default_value = "15s",
+        env = "KUBE_LEASE_DURATION",
+        value_parser = humantime::parse_duration
+    )]
+    pub lease_duration: Duration,
+
+    /// How long the leader keeps retrying to renew the lease before stopping the controller. Must
+    /// be shorter than the lease duration so the controller stops before another instance can
+    /// acquire the lease.
+    #[arg(
+        long = "kube-renew-deadline",
+        default_value = "10s",
+        env = "KUBE_RENEW_DEADLINE",
+        value_parser = humantime::parse_duration
+    )]
+    pub renew_deadline: Duration,
+
+    /// The identity to hold the lease as, defaults to the hostname
+    #[arg(long = "kube-identity", env = "KUBE_IDENTITY")]
+    pub identity: Option<String>,
+}
+
+impl Options {
+    /// The identity to hold the lease as
+    pub fn identity(&self) -> String {
+        self.identity
+            .clone()
+            .or_else(|| std::env::var("HOSTNAME").ok())
+            .filter(|identity| !identity.is_empty())
+            .unwrap_or_else(|| Uuid::new_v4().to_string())
+    }
+}
+
+/// Who holds the lease
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Leadership {
+    /// This instance holds the lease
+    Leader,
+    /// Another instance holds the lease, if anyone does
+    Follower(Option<String>),
+}
+
+/// Competes for a lease with the other replicas
+#[derive(Clone)]
+pub struct Elector {
+    api: Api<Lease>,
+    name: String,
+    identity: String,
+    duration: Duration,
+    renew_deadline: Duration,
+}
+
+impl Elector {
+    pub fn new(client: Client, options: &Options, identity: String) -> Self {
+        let mut renew_deadline = options.renew_deadline;
+        if renew_deadline >= options.lease_duration {
+            renew_deadline = options.lease_duration * 2 / 3;
+            warn!(
+                ?renew_deadline,
+                "renew deadline must be shorter than the lease duration"
+            );
+        }
+
+        Self {
+            api: Api::namespaced(client, &options.lease_namespace),
+            name: options.lease_name.clone(),
+            identity,
+            duration: options.lease_duration,
+            renew_deadline,
+        }
+    }
+
+    /// How often to retry acquiring or renewing the lease
+    pub fn retry_period(&self) -> Duration {
+        self.duration / 3
+    }
+
+    /// Wait until the lease is acquired, reporting the current leadership after each attempt
+    #[instrument(skip_all, fields(lease = %self.name, identity = %self.identity))]
+    pub async fn acquire(&self, report: impl Fn(Leadership)) {
+        loop {
+            match self.try_acquire_or_renew().await {
+                Ok(Leadership::Leader) => {
+                    info!("acquired lease");
+                    report(Leadership::Leader);
+                    return;
+                }
+                Ok(leadership) => {
+                    debug!(?leadership, "lease is held by another instance");
+                    report(leadership);
+                }
+                Err(error) => warn!(%error, "failed to acquire lease"),
+            }
+
+            time::sleep(self.retry_period()).await;
+        }
+    }
+
+    /// Keep renewing the lease, notifying the sender once it is lost or could not be renewed
+    /// before the renew deadline
+    #[instrument(skip_all, fields(lease = %self.name, identity = %self.identity))]
+    pub async fn hold(self, lost: oneshot::Sender<Leadership>) {
+        let mut last_renewed = time::Instant::now();
+
+        loop {
+            time::sleep(self.retry_period()).await;
+
+            // Stop leading while the lease is still valid so another instance cannot acquire it
+            // while the controller is running
+            let deadline = last_renewed + self.renew_deadline;
+            let attempted = time::Instant::now();
+            let result = match time::timeout_at(deadline, self.try_acquire_or_renew()).await {
+                Ok(result) => result,
+                Err(_) => {
+                    warn!("failed to renew lease before the renew deadline");
+                    let _ = lost.send(Leadership::Follower(None));
+                    return;
+                }
+            };
+
+            match result {
+                Ok(Leadership::Leader) => last_renewed = attempted,
+                Ok(leadership) => {
+                    warn!(?leadership, "lost lease");
+                    let _ = lost.send(leadership);
+                    return;
+                }
+                Err(error) if time::Instant::now() < deadline => {
+                    warn!(%error, "failed to renew lease")
+                }
+                Err(error) => {
+                    warn!(%error, "failed to renew lease before the renew deadline");
+                    let _ = lost.send(Leadership::Follower(None));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Give up the lease so another instance can take over without waiting for it to expire
+    #[instrument(skip_all, fields(lease = %self.name, identity = %self.identity))]
+    pub async fn release(&self) {
+        let result = async {
+            let Some(mut lease) = self.api.get_opt(&self.name).await? else {
+                return Ok(());
+            };
+
+            let spec = lease.spec.get_or_insert_with(Default::default);
+            if spec.holder_identity.as_ref() != Some(&self.identity) {
+                return Ok(());
+            }
+            spec.holder_identity = None;
+
+            self.api
+                .replace(&self.name, &PostParams::default(), &lease)
+                .await?;
+            info!("released lease");
+
+            Ok::<_, kube::Error>(())
+        }
+        .await;
+
+        if let Err(error) = result {
+            warn!(%error, "failed to release lease");
+        }
+    }
+
+    /// Attempt to acquire the lease, or renew it if it is already held
+    async fn try_acquire_or_renew(&self) -> Result<Leadership> {
+        let now = Utc::now();
+        let duration_seconds = self.duration.as_secs().max(1) as i32;
+
+        let Some(mut lease) = self.api.get_opt(&self.name).await? else {
+            let lease = Lease {
+                metadata: ObjectMeta {
+                    name: Some(self.name.clone()),
+                    ..Default::default()
+                },
+                spec: Some(LeaseSpec {
+                    holder_identity: Some(self.identity.clone()),
+                    lease_duration_seconds: Some(duration_seconds),
+                    acquire_time: Some(MicroTime(now)),
+                    renew_time: Some(MicroTime(now)),
+                    lease_transitions: Some(0),
+                }),
+            };
+
+            return match self.api.create(&PostParams::default(), &lease).await {
+                Ok(_) => Ok(Leadership::Leader),
+                Err(kube::Error::Api(response)) if response.code == 409 => {
+                    Ok(Leadership::Follower(None))
+                }
+                Err(error) => Err(error.into()),
+            };
+        };
+
+        let spec = lease.spec.get_or_insert_with(Default::default);
+        let holder = spec.holder_identity.clone().filter(|h| !h.is_empty());
+        let held_by_us = holder.as_ref() == Some(&self.identity);
+
+        if !held_by_us {
+            let expires = spec.renew_time.as_ref().map(|renewed| {
+                let duration = spec.lease_duration_seconds.unwrap_or(duration_seconds);
+                renewed.0 + chrono::Duration::seconds(i64::from(duration))
+            });
+            if holder.is_some() && expires.is_some_and(|expires| expires > now) {
+                return Ok(Leadership::Follower(holder));
+            }
+
+            spec.holder_identity = Some(self.identity.clone());
+            spec.acquire_time = Some(MicroTime(now));
+            spec.lease_transitions = Some(spec.lease_transitions.unwrap_or_default() + 1);
+        }
+        spec.lease_duration_seconds = Some(duration_seconds);
+        spec.renew_time = Some(MicroTime(now));
+
+        // The resource version is kept from the fetched lease, so this fails if another instance
+        // modified it in the meantime
+        match self
+            .api
+            .replace(&self.name, &PostParams::default(), &lease)
+            .await
+        {
+            Ok(_) => Ok(Leadership::Leader),
+            Err(kube::Error::Api(response)) if response.code == 409 => {
+                Ok(Leadership::Follower(holder.filter(|_| !held_by_us)))
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use super::{Database, DatabaseSecret, Result};
+use k8s_openapi::{api::core::v1::Namespace, apimachinery::pkg::apis::meta::v1::LabelSelector};
+use kube::{api::ListParams, client::Client, runtime::reflector::ObjectRef, Api, ResourceExt};
+use std::{collections::BTreeMap, sync::Arc};
+use tracing::{debug, instrument};
+
+/// Get the namespaces the connection secret should be written to: the ones listed explicitly, and
+/// any that match the selector and are not being deleted
+#[instrument(skip_all)]
+pub async fn desired(spec: &DatabaseSecret, client: Client) -> Result<Vec<String>> {
+    let mut desired = spec.namespaces.clone();
+    let Some(selector) = &spec.namespace_selector else {
+        return Ok(desired);
+    };
+
+    let namespaces = Api::<Namespace>::all(client)
+        .list(&ListParams::default())
+        .await?;
+    for namespace in namespaces {
+        let name = namespace.name_any();
+        if matches(selector, namespace.labels()) && !terminating(&namespace) {
+            if !desired.contains(&name) {
+                desired.push(name);
+            }
+        } else {
+            debug!(%name, "namespace does not match selector");
+        }
+    }
+
+    Ok(desired)
+}
+
+/// Find the databases whose secret may need to be written to or removed from the namespace
+pub fn affected(databases: Vec<Arc<Database>>, namespace: &Namespace) -> Vec<ObjectRef<Database>> {
+    let name = namespace.name_any();
+
+    databases
+        .into_iter()
+        .filter(|object| {
+            let Some(selector) = &object.spec.secret.namespace_selector else {
+                return false;
+            };
+
+            let written = object
+                .status
+                .as_ref()
+                .is_some_and(|status| status.secret_namespaces().contains(&name));
+            written || matches(selector, namespace.labels())
+        })
+        .map(|object| ObjectRef::from_obj(&*object))
+        .collect()
+}
+
+/// Whether the namespace is being deleted, in which case secrets can no longer be created in it
+fn terminating(namespace: &Namespace) -> bool {
+    let phase = namespace
+        .status
+        .as_ref()
+        .and_then(|status| status.phase.as_deref());
+    phase == Some("Terminating")
+}
+
+/// Check whether the labels are matched by the selector, following the semantics Kubernetes uses
+/// for label selectors. Unknown operators never match.
+fn matches(selector: &LabelSelector, labels: &BTreeMap<String, String>) -> bool {
+    let match_labels = selector
+        .match_labels
+        .iter()
+        .flatten()
+        .all(|(key, value)| labels.get(key) == Some(value));
+
+    let match_expressions = selector
+        .match_expressions
+        .iter()
+        .flatten()
+        .all(|requirement| {
+            let values = requirement.values.as_deref().unwrap_or_default();
+            let value = labels.get(&requirement.key);
+
+            match requirement.operator.as_str() {
+                "In" => value.is_some_and(|value| values.contains(value)),
+                "NotIn" => value.map_or(true, |value| !values.contains(value)),
+                "Exists" => value.is_some(),
+                "DoesNotExist" => value.is_none(),
+                _ => false,
+            }
+        });
+
+    match_labels && match_expressions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, LabelSelectorRequirement};
+    use std::collections::BTreeMap;
+
+    fn labels(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn expression(key: &str, operator: &str, values: &[&str]) -> LabelSelector {
+        LabelSelector {
+            match_expressions: Some(vec![LabelSelectorRequirement {
+                key: key.to_string(),
+                operator: operator.to_string(),
+                values: Some(values.iter().map(ToString::to_string).collect()),
+            }]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn empty_selector_matches_everything() {
+        let selector = LabelSelector::default();
+        assert!(matches(&selector, &labels(&[])));
+        assert!(matches(&selector, &labels(&[("team", "web")])));
+    }
+
+    #[test]
+    fn match_labels() {
+        let selector = LabelSelector {
+            match_labels: Some(labels(&[("team", "web"), ("env", "prod")])),
+            ..Default::default()
+        };
+
+        assert!(matches(
+            &selector,
+            &labels(&[("team", "web"), ("env", "prod"), ("extra", "yes")])
+        ));
+        assert!(!matches(&selector, &labels(&[("team", "web")])));
+        assert!(!matches(
+            &selector,
+            &labels(&[("team", "web"), ("env", "staging")])
+        ));
+    }
+
+    #[test]
+    fn in_operator() {
+        let selector = expression("env", "In", &["prod", "staging"]);
+        assert!(matches(&selector, &labels(&[("env", "staging")])));
+        assert!(!matches(&selector, &labels(&[("env", "dev")])));
+        assert!(!matches(&selector, &labels(&[])));
+    }
+
+    #[test]
+    fn not_in_operator() {
+        let selector = expression("env", "NotIn", &["prod"]);
+        assert!(matches(&selector, &labels(&[("env", "dev")])));
+        assert!(matches(&selector, &labels(&[])));
+        assert!(!matches(&selector, &labels(&[("env", "prod")])));
+    }
+
+    #[test]
+    fn exists_operators() {
+        let exists = expression("team", "Exists", &[]);
+        assert!(matches(&exists, &labels(&[("team", "")])));
+        assert!(!matches(&exists, &labels(&[])));
+
+        let does_not_exist = expression("team", "DoesNotExist", &[]);
+        assert!(matches(&does_not_exist, &labels(&[])));
+        assert!(!matches(&does_not_exist, &labels(&[("team", "web")])));
+    }
+
+    #[test]
+    fn unknown_operator_never_matches() {
+        let selector = expression("env", "Gt", &["1"]);
+        assert!(!matches(&selector, &labels(&[("env", "2")])));
+    }
+
+    #[test]
+    fn labels_and_expressions_must_both_match() {
+        let selector = LabelSelector {
+            match_labels: Some(labels(&[("team", "web")])),
+            ..expression("env", "In", &["prod"])
+        };
+
+        assert!(matches(
+            &selector,
+            &labels(&[("team", "web"), ("env", "prod")])
+        ));
+        assert!(!matches(&selector, &labels(&[("team", "web")])));
+        assert!(!matches(&selector, &labels(&[("env", "prod")])));
+    }
+}
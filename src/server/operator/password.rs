@@ -0,0 +1,143 @@
+use super::{Database, DatabasePasswordGenerate, Error, Result};
+use chrono::{DateTime, Utc};
+use k8s_openapi::{api::core::v1::Secret, apimachinery::pkg::apis::meta::v1::ObjectMeta};
+use kube::{
+    api::{Patch, PatchParams, PostParams},
+    client::Client,
+    Api, Resource, ResourceExt,
+};
+use rand::{rngs::OsRng, seq::SliceRandom};
+use serde_json::json;
+use std::collections::BTreeMap;
+use tracing::{info, instrument};
+
+/// The key the generated password is stored under
+const KEY: &str = "password";
+
+/// The annotation recording when the password was last generated
+const ROTATED_AT_ANNOTATION: &str = "external-postgres.wafflehacks.cloud/rotated-at";
+
+/// A password generated by the operator
+#[derive(Debug)]
+pub struct Generated {
+    pub password: String,
+    pub rotated_at: DateTime<Utc>,
+}
+
+/// Retrieve the generated password for the database, creating it if it does not exist yet
+#[instrument(skip_all, fields(namespace = %spec.namespace(client.default_namespace())))]
+pub async fn generated(
+    object: &Database,
+    spec: &DatabasePasswordGenerate,
+    client: Client,
+) -> Result<Generated> {
+    let name = secret_name(object);
+    let namespace = spec.namespace(client.default_namespace()).to_string();
+    let secrets = Api::<Secret>::namespaced(client, &namespace);
+
+    if let Some(secret) = secrets.get_opt(&name).await? {
+        info!(%name, "found generated password secret");
+
+        let rotated_at = secret
+            .metadata
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(ROTATED_AT_ANNOTATION))
+            .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+            .map(|value| value.with_timezone(&Utc))
+            .or_else(|| secret.metadata.creation_timestamp.as_ref().map(|t| t.0))
+            .unwrap_or_else(Utc::now);
+
+        let password_bytes = secret
+            .data
+            .unwrap_or_default()
+            .remove(KEY)
+            .ok_or(Error::NoPassword)?;
+        let password = String::from_utf8(password_bytes.0).map_err(|_| Error::InvalidPassword)?;
+
+        return Ok(Generated {
+            password,
+            rotated_at,
+        });
+    }
+
+    let generated = generate(spec);
+    secrets
+        .create(
+            &PostParams::default(),
+            &Secret {
+                metadata: ObjectMeta {
+                    name: Some(name.clone()),
+                    annotations: Some(annotations(&generated)),
+                    owner_references: object.controller_owner_ref(&()).map(|r| vec![r]),
+                    ..Default::default()
+                },
+                string_data: Some(BTreeMap::from([(
+                    KEY.to_string(),
+                    generated.password.clone(),
+                )])),
+                ..Default::default()
+            },
+        )
+        .await?;
+    info!(%name, "stored newly generated password");
+
+    Ok(generated)
+}
+
+/// Replace the generated password for the database with a new one
+#[instrument(skip_all, fields(namespace = %spec.namespace(client.default_namespace())))]
+pub async fn rotate(
+    object: &Database,
+    spec: &DatabasePasswordGenerate,
+    client: Client,
+) -> Result<Generated> {
+    let name = secret_name(object);
+    let namespace = spec.namespace(client.default_namespace()).to_string();
+    let secrets = Api::<Secret>::namespaced(client, &namespace);
+
+    let generated = generate(spec);
+    secrets
+        .patch(
+            &name,
+            &PatchParams::default(),
+            &Patch::Merge(json!({
+                "metadata": {
+                    "annotations": annotations(&generated),
+                },
+                "stringData": {
+                    KEY: generated.password,
+                },
+            })),
+        )
+        .await?;
+    info!(%name, "rotated generated password");
+
+    Ok(generated)
+}
+
+/// Generate a new random password
+fn generate(spec: &DatabasePasswordGenerate) -> Generated {
+    let charset = spec.charset.characters();
+    let password = (0..spec.length)
+        .map(|_| *charset.choose(&mut OsRng).unwrap() as char)
+        .collect();
+
+    Generated {
+        password,
+        rotated_at: Utc::now(),
+    }
+}
+
+/// The annotations to store alongside the generated password
+fn annotations(generated: &Generated) -> BTreeMap<String, String> {
+    BTreeMap::from([(
+        ROTATED_AT_ANNOTATION.to_string(),
+        generated.rotated_at.to_rfc3339(),
+    )])
+}
+
+/// The name of the secret the generated password is stored in
+pub fn secret_name(object: &Database) -> String {
+    format!("database-{}-password", object.name_any())
+}
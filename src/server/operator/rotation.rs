@@ -0,0 +1,164 @@
+use super::{Database, DatabaseRotation, Error, Result};
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use kube::{runtime::controller::Action, ResourceExt};
+use std::{str::FromStr, time::Duration};
+
+/// The annotation used to request a rotation through the management API
+pub const REQUESTED_AT_ANNOTATION: &str = "external-postgres.wafflehacks.cloud/rotate-requested-at";
+
+impl DatabaseRotation {
+    /// Determine when the password should next be rotated
+    pub fn next(&self, last: DateTime<Utc>) -> Result<DateTime<Utc>> {
+        match (&self.interval, &self.schedule) {
+            (Some(interval), None) => {
+                let interval = humantime::parse_duration(interval)
+                    .map_err(|e| Error::InvalidRotation(format!("invalid interval: {e}")))?;
+                let interval = chrono::Duration::from_std(interval)
+                    .map_err(|_| Error::InvalidRotation(String::from("interval is too long")))?;
+
+                Ok(last + interval)
+            }
+            (None, Some(schedule)) => parse_schedule(schedule)?
+                .after(&last)
+                .next()
+                .ok_or_else(|| Error::InvalidRotation(String::from("schedule never occurs"))),
+            _ => Err(Error::InvalidRotation(String::from(
+                "exactly one of interval or schedule must be set",
+            ))),
+        }
+    }
+
+    /// Check whether the password is due to be rotated
+    pub fn due(&self, last: DateTime<Utc>) -> Result<bool> {
+        Ok(self.next(last)? <= Utc::now())
+    }
+
+    /// Determine when the object should next be reconciled so the password gets rotated on time,
+    /// waiting no longer than `resync`
+    pub fn requeue(&self, last: DateTime<Utc>, resync: Duration) -> Result<Action> {
+        let remaining = (self.next(last)? - Utc::now()).to_std().unwrap_or_default();
+
+        Ok(Action::requeue(remaining.clamp(
+            Duration::from_secs(1),
+            resync.max(Duration::from_secs(1)),
+        )))
+    }
+}
+
+/// Check whether a rotation was requested through the management API since the last rotation
+pub fn requested(object: &Database, last: DateTime<Utc>) -> bool {
+    object
+        .annotations()
+        .get(REQUESTED_AT_ANNOTATION)
+        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+        .map(|requested| requested > last)
+        .unwrap_or_default()
+}
+
+/// Parse a cron expression, accepting both the standard 5 field and the extended 6 field formats
+fn parse_schedule(schedule: &str) -> Result<Schedule> {
+    let schedule = match schedule.split_whitespace().count() {
+        5 => format!("0 {schedule}"),
+        _ => schedule.to_string(),
+    };
+
+    Schedule::from_str(&schedule)
+        .map_err(|e| Error::InvalidRotation(format!("invalid schedule: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DatabaseRotation;
+    use chrono::{DateTime, TimeZone, Utc};
+    use kube::runtime::controller::Action;
+    use std::time::Duration;
+
+    fn interval(interval: &str) -> DatabaseRotation {
+        DatabaseRotation {
+            interval: Some(interval.to_string()),
+            schedule: None,
+        }
+    }
+
+    fn schedule(schedule: &str) -> DatabaseRotation {
+        DatabaseRotation {
+            interval: None,
+            schedule: Some(schedule.to_string()),
+        }
+    }
+
+    /// A Monday
+    fn monday() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 3, 6, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn interval_is_added_to_last_rotation() {
+        let next = interval("30d").next(monday()).unwrap();
+        assert_eq!(next, Utc.with_ymd_and_hms(2023, 4, 5, 12, 0, 0).unwrap());
+
+        let next = interval("12h 30m").next(monday()).unwrap();
+        assert_eq!(next, Utc.with_ymd_and_hms(2023, 3, 7, 0, 30, 0).unwrap());
+    }
+
+    #[test]
+    fn invalid_interval() {
+        assert!(interval("monthly").next(monday()).is_err());
+        assert!(interval("").next(monday()).is_err());
+    }
+
+    #[test]
+    fn standard_schedule() {
+        let next = schedule("0 4 * * SUN").next(monday()).unwrap();
+        assert_eq!(next, Utc.with_ymd_and_hms(2023, 3, 12, 4, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn extended_schedule() {
+        let next = schedule("30 0 4 * * SUN").next(monday()).unwrap();
+        assert_eq!(next, Utc.with_ymd_and_hms(2023, 3, 12, 4, 0, 30).unwrap());
+    }
+
+    #[test]
+    fn invalid_schedule() {
+        assert!(schedule("every sunday").next(monday()).is_err());
+        assert!(schedule("0 25 * * *").next(monday()).is_err());
+    }
+
+    #[test]
+    fn exactly_one_of_interval_or_schedule() {
+        let both = DatabaseRotation {
+            interval: Some(String::from("30d")),
+            schedule: Some(String::from("0 4 * * SUN")),
+        };
+        assert!(both.next(monday()).is_err());
+
+        let neither = DatabaseRotation {
+            interval: None,
+            schedule: None,
+        };
+        assert!(neither.next(monday()).is_err());
+    }
+
+    #[test]
+    fn due() {
+        assert!(interval("1h").due(monday()).unwrap());
+        assert!(!interval("1h").due(Utc::now()).unwrap());
+    }
+
+    #[test]
+    fn requeue_waits_at_most_resync() {
+        let resync = Duration::from_secs(600);
+        let action = interval("30d").requeue(Utc::now(), resync).unwrap();
+        assert_eq!(action, Action::requeue(resync));
+    }
+
+    #[test]
+    fn requeue_overdue_rotation_soon() {
+        let action = interval("1h")
+            .requeue(monday(), Duration::from_secs(600))
+            .unwrap();
+        assert_eq!(action, Action::requeue(Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,173 @@
+use super::{Database, Result};
+use k8s_openapi::api::core::v1::Secret;
+use kube::{
+    api::{DeleteParams, ListParams},
+    client::Client,
+    Api, ResourceExt,
+};
+use std::collections::BTreeMap;
+use tracing::{info, instrument};
+
+/// The label identifying the database a connection secret was written for
+pub const DATABASE_UID_LABEL: &str = "external-postgres.wafflehacks.cloud/database-uid";
+/// The label containing the name of the PostgreSQL database
+const DATABASE_NAME_LABEL: &str = "external-postgres.wafflehacks.cloud/database";
+/// The label containing the name of the cluster the operator is running for
+const CLUSTER_LABEL: &str = "external-postgres.wafflehacks.cloud/cluster";
+/// The standard label identifying the tool that manages the secret
+const MANAGED_BY_LABEL: &str = "app.kubernetes.io/managed-by";
+
+/// The labels to apply to every connection secret written for the database. Any custom labels from
+/// the spec are included, but cannot replace the ones set by the operator.
+pub fn labels(object: &Database, database: &str, cluster: &str) -> BTreeMap<String, String> {
+    let mut labels = object.spec.secret.labels.clone();
+    labels.extend([
+        (
+            String::from(MANAGED_BY_LABEL),
+            String::from("external-postgres"),
+        ),
+        (
+            String::from(DATABASE_UID_LABEL),
+            object.uid().unwrap_or_default(),
+        ),
+        (String::from(DATABASE_NAME_LABEL), label_value(database)),
+        (String::from(CLUSTER_LABEL), label_value(cluster)),
+    ]);
+
+    labels
+}
+
+/// Convert an arbitrary string into a valid label value by replacing disallowed characters, and
+/// trimming it to the maximum length
+fn label_value(value: &str) -> String {
+    let replaced = value
+        .chars()
+        .map(|c| match c {
+            'A'..='Z' | 'a'..='z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '-',
+        })
+        .take(63)
+        .collect::<String>();
+
+    // Values must begin and end with an alphanumeric character
+    replaced
+        .trim_matches(|c: char| !c.is_ascii_alphanumeric())
+        .to_string()
+}
+
+/// Delete the database's connection secrets that are not named `name` in one of the namespaces,
+/// returning the namespace and name of each one deleted
+#[instrument(skip_all)]
+pub async fn prune(
+    object: &Database,
+    name: &str,
+    namespaces: &[String],
+    client: Client,
+) -> Result<Vec<String>> {
+    let mut removed = Vec::new();
+
+    for secret in owned(object, client.clone()).await? {
+        let namespace = secret.namespace().unwrap_or_default();
+        let secret_name = secret.name_any();
+        if secret_name == name && namespaces.contains(&namespace) {
+            continue;
+        }
+
+        delete(&namespace, &secret_name, client.clone()).await?;
+        removed.push(format!("{namespace}/{secret_name}"));
+    }
+
+    Ok(removed)
+}
+
+/// Delete all the database's connection secrets, returning the namespace and name of each one
+#[instrument(skip_all)]
+pub async fn remove_all(object: &Database, client: Client) -> Result<Vec<String>> {
+    prune(object, "", &[], client).await
+}
+
+/// Find the database's connection secrets in every namespace
+async fn owned(object: &Database, client: Client) -> Result<Vec<Secret>> {
+    // Without a UID, the selector would match secrets written for other databases
+    let Some(uid) = object.uid() else {
+        return Ok(Vec::new());
+    };
+
+    let secrets = Api::<Secret>::all(client)
+        .list(&ListParams::default().labels(&format!("{DATABASE_UID_LABEL}={uid}")))
+        .await?;
+
+    Ok(secrets.items)
+}
+
+/// Delete a secret, ignoring it if it was already deleted
+async fn delete(namespace: &str, name: &str, client: Client) -> Result<()> {
+    let secrets = Api::<Secret>::namespaced(client, namespace);
+    match secrets.delete(name, &DeleteParams::default()).await {
+        Ok(_) => {}
+        Err(kube::Error::Api(response)) if response.code == 404 => {}
+        Err(error) => return Err(error.into()),
+    }
+    info!(%namespace, %name, "removed secret");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{label_value, labels, Database, DATABASE_NAME_LABEL, MANAGED_BY_LABEL};
+    use serde_json::json;
+
+    #[test]
+    fn valid_values_are_unchanged() {
+        assert_eq!(label_value("my_app-1.db"), "my_app-1.db");
+    }
+
+    #[test]
+    fn invalid_characters_are_replaced() {
+        assert_eq!(label_value("my app/db"), "my-app-db");
+        assert_eq!(label_value("pässwörd"), "p-ssw-rd");
+    }
+
+    #[test]
+    fn ends_are_alphanumeric() {
+        assert_eq!(label_value("_app_"), "app");
+        assert_eq!(label_value("-.app.-"), "app");
+        assert_eq!(label_value("$$$"), "");
+        assert_eq!(label_value(""), "");
+    }
+
+    #[test]
+    fn truncated_to_maximum_length() {
+        let long = "a".repeat(100);
+        assert_eq!(label_value(&long), "a".repeat(63));
+
+        // Trimming happens after truncation, so the result never ends with a separator
+        let long = format!("{}_{}", "a".repeat(62), "b".repeat(10));
+        assert_eq!(label_value(&long), "a".repeat(62));
+    }
+
+    #[test]
+    fn operator_labels_take_precedence() {
+        let object: Database = serde_json::from_value(json!({
+            "apiVersion": "external-postgres.wafflehacks.cloud/v1",
+            "kind": "Database",
+            "metadata": { "name": "app" },
+            "spec": {
+                "password": { "value": "secret" },
+                "secret": {
+                    "labels": {
+                        "team": "web",
+                        "app.kubernetes.io/managed-by": "someone-else",
+                    },
+                },
+            },
+        }))
+        .unwrap();
+
+        let labels = labels(&object, "my app", "default");
+        assert_eq!(labels["team"], "web");
+        assert_eq!(labels[MANAGED_BY_LABEL], "external-postgres");
+        assert_eq!(labels[DATABASE_NAME_LABEL], "my-app");
+    }
+}
@@ -0,0 +1,42 @@
+use crate::models::operator::ControllerState;
+use chrono::{DateTime, Utc};
+
+/// Tracks the lifecycle of the controller task
+#[derive(Clone, Debug)]
+pub struct State {
+    pub state: ControllerState,
+    /// When the controller entered the current state
+    pub since: DateTime<Utc>,
+    /// The error from the most recent failure
+    pub last_error: Option<String>,
+    /// When the most recent failure occurred
+    pub last_error_time: Option<DateTime<Utc>>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            state: ControllerState::Stopped,
+            since: Utc::now(),
+            last_error: None,
+            last_error_time: None,
+        }
+    }
+}
+
+impl State {
+    /// Move to a new state, only updating the timestamp if it changed
+    pub fn transition(&mut self, state: ControllerState) {
+        if self.state != state {
+            self.state = state;
+            self.since = Utc::now();
+        }
+    }
+
+    /// Move to the failed state with the given error
+    pub fn failed(&mut self, error: String) {
+        self.transition(ControllerState::Failed);
+        self.last_error = Some(error);
+        self.last_error_time = Some(self.since);
+    }
+}
@@ -0,0 +1,240 @@
+use super::Database;
+use chrono::{DateTime, Utc};
+use kube::{
+    api::{Patch, PatchParams},
+    client::Client,
+    Api, ResourceExt,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{debug, instrument};
+
+/// The observed state of the database
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatabaseStatus {
+    /// The generation of the spec that was last reconciled
+    observed_generation: Option<i64>,
+    /// The current state of the database
+    #[serde(default)]
+    conditions: Vec<Condition>,
+    /// The error from the most recent failed reconcile
+    last_error: Option<String>,
+    /// The namespaces the connection secret was written to
+    #[serde(default)]
+    secret_namespaces: Vec<String>,
+    /// When the generated password was last rotated
+    last_rotation_time: Option<DateTime<Utc>>,
+    /// The name of the database in PostgreSQL
+    database_name: Option<String>,
+    /// The name of the role that owns the database in PostgreSQL
+    username: Option<String>,
+    /// A hash of the connection secret's contents when it was last written
+    secret_data_hash: Option<String>,
+    /// A hash of the password the owner was last provisioned with
+    password_hash: Option<String>,
+    /// The differences from the desired state found by the most recent drift check that found any
+    #[serde(default)]
+    last_drift: Vec<String>,
+    /// When drift was last detected
+    last_drift_time: Option<DateTime<Utc>>,
+}
+
+impl DatabaseStatus {
+    /// Start building a new status from the object's current status
+    pub fn from_object(object: &Database) -> Self {
+        let mut status = object.status.clone().unwrap_or_default();
+        status.observed_generation = object.metadata.generation;
+        status
+    }
+
+    /// Set the state of a condition, only updating the transition time if the state changed
+    pub fn set(
+        &mut self,
+        type_: ConditionType,
+        status: bool,
+        reason: &str,
+        message: impl Into<String>,
+    ) {
+        let status = ConditionStatus::from(status);
+        let message = message.into();
+
+        if let Some(condition) = self.conditions.iter_mut().find(|c| c.type_ == type_) {
+            if condition.status != status {
+                condition.last_transition_time = Utc::now();
+            }
+            condition.status = status;
+            condition.reason = reason.to_string();
+            condition.message = message;
+        } else {
+            self.conditions.push(Condition {
+                type_,
+                status,
+                reason: reason.to_string(),
+                message,
+                last_transition_time: Utc::now(),
+            });
+        }
+    }
+
+    /// Record when the generated password was last rotated
+    pub fn rotated(&mut self, at: DateTime<Utc>) {
+        self.last_rotation_time = Some(at);
+    }
+
+    /// When the generated password was last rotated
+    pub fn last_rotation_time(&self) -> Option<DateTime<Utc>> {
+        self.last_rotation_time
+    }
+
+    /// Record the names the database and its owner were provisioned with, along with the hash of
+    /// the owner's password. Returns whether the password differs from the one previously recorded.
+    pub fn provisioned(
+        &mut self,
+        database_name: &str,
+        username: &str,
+        password_hash: String,
+    ) -> bool {
+        self.database_name = Some(database_name.to_string());
+        self.username = Some(username.to_string());
+
+        let previous = self.password_hash.replace(password_hash);
+        previous.is_some() && previous != self.password_hash
+    }
+
+    /// The name the database was provisioned with
+    pub fn database_name(&self) -> Option<&str> {
+        self.database_name.as_deref()
+    }
+
+    /// The name the database's owner was provisioned with
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    /// The namespaces the connection secret was last written to
+    pub fn secret_namespaces(&self) -> &[String] {
+        &self.secret_namespaces
+    }
+
+    /// The hash of the connection secret's contents when it was last written
+    pub fn secret_data_hash(&self) -> Option<&str> {
+        self.secret_data_hash.as_deref()
+    }
+
+    /// Record the result of a drift check
+    pub fn drift_checked(&mut self, drift: Vec<String>) {
+        if drift.is_empty() {
+            // Keep the reason from the last repair, only initializing the condition if it is missing
+            if !self
+                .conditions
+                .iter()
+                .any(|c| c.type_ == ConditionType::Drifted)
+            {
+                self.set(ConditionType::Drifted, false, "NoDrift", "");
+            }
+            return;
+        }
+
+        self.set(
+            ConditionType::Drifted,
+            true,
+            "DriftDetected",
+            drift.join("; "),
+        );
+        self.last_drift = drift;
+        self.last_drift_time = Some(Utc::now());
+    }
+
+    /// Mark the reconcile as successful
+    pub fn succeeded(&mut self, secret_namespaces: Vec<String>, secret_data_hash: String) {
+        self.set(ConditionType::Ready, true, "Reconciled", "");
+        if self.is(ConditionType::Drifted, true) {
+            self.set(ConditionType::Drifted, false, "Repaired", "");
+        }
+        self.last_error = None;
+        self.secret_namespaces = secret_namespaces;
+        self.secret_data_hash = Some(secret_data_hash);
+    }
+
+    /// Check whether a condition has the given state
+    fn is(&self, type_: ConditionType, status: bool) -> bool {
+        let status = ConditionStatus::from(status);
+        self.conditions
+            .iter()
+            .any(|c| c.type_ == type_ && c.status == status)
+    }
+
+    /// Mark the reconcile as failed with the given error. Errors that are not transient will not be
+    /// retried until the spec or environment changes.
+    pub fn failed(&mut self, error: &impl ToString, transient: bool) {
+        let message = error.to_string();
+        let reason = match transient {
+            true => "ReconcileFailed",
+            false => "PermanentFailure",
+        };
+        self.set(ConditionType::Ready, false, reason, &message);
+        self.last_error = Some(message);
+    }
+
+    /// Write the status to the object
+    #[instrument(skip_all, fields(name = %object.name_any()))]
+    pub async fn patch(&self, object: &Database, client: Client) -> kube::Result<()> {
+        let api = Api::<Database>::all(client);
+        api.patch_status(
+            &object.name_any(),
+            &PatchParams::default(),
+            &Patch::Merge(json!({ "status": self })),
+        )
+        .await?;
+        debug!("patched status");
+
+        Ok(())
+    }
+}
+
+/// Details about one aspect of the database's state
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Condition {
+    /// The aspect of the database's state
+    #[serde(rename = "type")]
+    type_: ConditionType,
+    /// Whether the condition is met
+    status: ConditionStatus,
+    /// A machine-readable reason for the condition's last transition
+    reason: String,
+    /// A human-readable description of the condition's state
+    #[serde(default)]
+    message: String,
+    /// When the condition last changed status
+    last_transition_time: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum ConditionType {
+    /// The database and connection secrets were fully reconciled
+    Ready,
+    /// The connection secret was written to every namespace
+    SecretsSynced,
+    /// The password could be retrieved from the spec
+    PasswordResolved,
+    /// The live state differed from the desired state and has not been repaired yet
+    Drifted,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum ConditionStatus {
+    True,
+    False,
+}
+
+impl From<bool> for ConditionStatus {
+    fn from(value: bool) -> Self {
+        match value {
+            true => Self::True,
+            false => Self::False,
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use super::{ConfigSource, Operator};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::Path,
+    time::Duration,
+};
+use tokio::{fs, time};
+use tracing::{debug, info, instrument, warn};
+
+/// Watch the kubeconfig, starting the operator when it appears and restarting it when it changes
+#[instrument(skip_all, fields(cluster = %operator.0.cluster))]
+pub async fn watch(operator: Operator, interval: Duration) {
+    if operator.0.config_source == ConfigSource::InCluster {
+        debug!("using in-cluster configuration, not watching kubeconfig");
+        return;
+    }
+
+    let mut watched = operator.kubeconfig_path();
+    let mut last = fingerprint(&watched).await;
+    let mut timer = time::interval(interval);
+    timer.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+    loop {
+        timer.tick().await;
+
+        let path = operator.kubeconfig_path();
+        let current = fingerprint(&path).await;
+
+        // The controller was already restarted when the path was changed
+        if path != watched {
+            debug!(path = %path.display(), "watching new kubeconfig");
+            watched = path;
+            last = current;
+            continue;
+        }
+
+        if current == last {
+            continue;
+        }
+
+        match (last, current) {
+            (None, Some(_)) => {
+                info!(path = %path.display(), "kubeconfig created");
+                operator.restart().await;
+            }
+            (Some(_), Some(_)) => {
+                info!(path = %path.display(), "kubeconfig modified");
+                operator.restart().await;
+            }
+            (Some(_), None) => warn!(
+                path = %path.display(),
+                "kubeconfig removed, the operator will keep using the previous credentials"
+            ),
+            (None, None) => unreachable!(),
+        }
+
+        last = current;
+    }
+}
+
+/// Identify the contents of the kubeconfig so changes can be detected
+async fn fingerprint(path: &Path) -> Option<u64> {
+    let contents = fs::read(path).await.ok()?;
+
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    Some(hasher.finish())
+}